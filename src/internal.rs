@@ -1,4 +1,8 @@
 pub mod ast;
+pub mod diagnostic;
 pub mod interpreter;
 pub mod parser;
+pub mod resolver;
+pub mod runtime;
 pub mod token;
+pub mod vm;
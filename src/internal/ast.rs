@@ -1,47 +1,207 @@
-use super::token::{Literal, Token};
+use std::rc::Rc;
+
+use super::token::{Literal, Span, Token};
+
+/// A statement and the source code it was parsed from.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
 
 #[derive(PartialEq, Debug, Clone)]
-pub enum Stmt {
+pub enum StmtKind {
     Function {
         name: Token,
-        params: Vec<Token>,
-        body: Vec<Stmt>,
+        // The parameters and body of a function are shared by every function
+        // value created from it, so creating and copying functions does not
+        // copy their code.
+        params: Rc<Vec<Param>>,
+        body: Rc<Vec<Stmt>>,
     },
     While {
+        label: Option<Token>,
         test: Expr,
         body: Vec<Stmt>,
     },
+    For {
+        label: Option<Token>,
+        name: Token,
+        iterable: Expr,
+        body: Vec<Stmt>,
+    },
     If {
         test: Expr,
         body: Vec<Stmt>,
         or_else: Option<Vec<Stmt>>,
     },
+    Break {
+        keyword: Token,
+        label: Option<Token>,
+    },
+    Continue {
+        keyword: Token,
+        label: Option<Token>,
+    },
     Return {
         keyword: Token,
         value: Option<Expr>,
     },
-    Delete(Vec<Token>),
+    Delete(Vec<Expr>),
     Expr(Expr),
     Echo(Expr),
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    Try {
+        keyword: Token,
+        body: Vec<Stmt>,
+        catch: Option<Catch>,
+        finally: Option<Vec<Stmt>>,
+    },
+    Match {
+        keyword: Token,
+        subject: Expr,
+        arms: Vec<MatchArm>,
+    },
+    Assert {
+        keyword: Token,
+        test: Expr,
+        // Source code of the test, shown when the assertion fails
+        source: String,
+        message: Option<Expr>,
+    },
 }
 
+/// A parameter of a function.
 #[derive(PartialEq, Debug, Clone)]
-pub enum Expr {
+pub struct Param {
+    pub name: Token,
+    pub kind: ParamKind,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum ParamKind {
+    Required,
+    /// A parameter with a default value, computed when a call leaves it out
+    Optional(Box<Expr>),
+    /// The last parameter, collecting the extra arguments into a list
+    Rest,
+}
+
+/// An arm of a `match` statement, which runs its body if any of its patterns
+/// matches.
+#[derive(PartialEq, Debug, Clone)]
+pub struct MatchArm {
+    pub patterns: Vec<Pattern>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum PatternKind {
+    /// Matches values equal to the literal
+    Literal(Literal),
+    /// Matches values from `start` up to, but not including, `end`
+    Range { start: Literal, end: Literal },
+    /// Matches any value
+    Wildcard,
+}
+
+/// The `catch` clause of a `try` statement, which assigns the caught error to
+/// a variable like an assignment expression does.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Catch {
+    pub name: Token,
+    pub depth: Option<usize>,
+    pub body: Vec<Stmt>,
+}
+
+/// An expression and the source code it was parsed from.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+// The `depth` of a variable is the number of scopes between its use and its
+// definition. It is filled in by the resolver, and `None` means the variable
+// is global.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ExprKind {
     Binary(Box<Expr>, Token, Box<Expr>),
     Unary(Token, Box<Expr>),
     Grouping(Box<Expr>),
-    Assign(Token, Box<Expr>),
+    Assign {
+        name: Token,
+        value: Box<Expr>,
+        depth: Option<usize>,
+    },
     Logical(Box<Expr>, Token, Box<Expr>),
+    /// `test ? then : or_else`
+    Conditional {
+        test: Box<Expr>,
+        then: Box<Expr>,
+        or_else: Box<Expr>,
+    },
     Call(Box<Expr>, Token, Vec<Expr>),
     Constant(Literal),
-    Variable(Token),
+    Variable {
+        name: Token,
+        depth: Option<usize>,
+    },
+    Interpolation(Vec<Expr>),
+    Lambda {
+        keyword: Token,
+        // Shared like the parameters and body of a function statement
+        params: Rc<Vec<Param>>,
+        body: Rc<Vec<Stmt>>,
+    },
+    List(Vec<Expr>),
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    },
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
     AugAssign {
         name: Token,
         operator: Token,
         value: Box<Expr>,
+        depth: Option<usize>,
     },
     Prefix {
         operator: Token,
         name: Token,
+        depth: Option<usize>,
     },
 }
+
+impl Stmt {
+    /// Creates a new `Stmt`.
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl Expr {
+    /// Creates a new `Expr`.
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
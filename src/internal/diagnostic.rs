@@ -0,0 +1,295 @@
+use std::env;
+use std::io::{self, IsTerminal};
+
+use crate::internal::token::Span;
+
+// ANSI escape codes used when writing to a terminal
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Width of a tab character in source snippets.
+const TAB_WIDTH: usize = 4;
+
+/// A named source text that diagnostics point into.
+pub struct Source<'a> {
+    pub name: &'a str,
+    pub text: &'a str,
+}
+
+/// An error or warning message about a range of source code, with optional
+/// notes and help text.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    severity: Severity,
+    kind: String,
+    message: String,
+    span: Span,
+    label: Option<String>,
+    notes: Vec<String>,
+    help: Option<String>,
+}
+
+/// Whether a diagnostic stops the program from running.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Diagnostic {
+    /// Creates a new `Diagnostic` of the given error kind.
+    pub fn new(kind: &str, message: &str, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            kind: kind.to_string(),
+            message: message.to_string(),
+            span,
+            label: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    /// Creates a new `Diagnostic` about code that runs but is likely a
+    /// mistake.
+    pub fn warning(message: &str, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new("Warning", message, span)
+        }
+    }
+
+    /// Returns whether the diagnostic is an error or a warning.
+    #[cfg(test)]
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Sets the text shown next to the underline.
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Adds a note below the source snippet.
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Sets a suggestion on how to fix the error.
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    /// Prints the diagnostic to stderr, colored if stderr is a terminal.
+    pub fn emit(&self, source: &Source) {
+        let color = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+        eprint!("{}", self.render(source, color));
+    }
+
+    /// Formats the diagnostic with the source line it points to.
+    pub fn render(&self, source: &Source, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{style}{text}{RESET}")
+            } else {
+                text.to_string()
+            }
+        };
+        let accent = match self.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+
+        let mut output = format!(
+            "{}{}\n",
+            paint(accent, &format!("{}:", self.kind)),
+            paint(BOLD, &format!(" {}", self.message))
+        );
+
+        let Some(snippet) = Snippet::new(source.text, self.span) else {
+            // The span is outside the source, so only the name can be shown
+            output.push_str(&format!("{} {}\n", paint(BLUE, "-->"), source.name));
+            return output;
+        };
+
+        let number = snippet.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let bar = paint(BLUE, "|");
+        let underline = format!(
+            "{}{}",
+            "^".repeat(snippet.width),
+            self.label
+                .as_ref()
+                .map(|label| format!(" {label}"))
+                .unwrap_or_default()
+        );
+
+        output.push_str(&format!(
+            "{gutter}{} {}:{}:{}\n",
+            paint(BLUE, "-->"),
+            source.name,
+            snippet.line,
+            snippet.column
+        ));
+        output.push_str(&format!("{gutter} {bar}\n"));
+        output.push_str(&format!(
+            "{} {bar} {}\n",
+            paint(BLUE, &number),
+            snippet.text
+        ));
+        output.push_str(&format!(
+            "{gutter} {bar} {}{}\n",
+            " ".repeat(snippet.offset),
+            paint(accent, &underline)
+        ));
+
+        if !self.notes.is_empty() || self.help.is_some() {
+            output.push_str(&format!("{gutter} {bar}\n"));
+        }
+        for note in &self.notes {
+            output.push_str(&format!("{gutter} {} {note}\n", paint(BOLD, "= note:")));
+        }
+        if let Some(help) = &self.help {
+            output.push_str(&format!("{gutter} {} {help}\n", paint(BOLD, "= help:")));
+        }
+
+        output
+    }
+}
+
+/// The source line a span starts on, prepared for display.
+struct Snippet {
+    line: usize,
+    column: usize,
+    text: String,
+    // Display width before and inside the underlined range
+    offset: usize,
+    width: usize,
+}
+
+impl Snippet {
+    /// Finds the line of a span. Returns `None` if the span is outside the
+    /// text.
+    fn new(text: &str, span: Span) -> Option<Self> {
+        if span.start > span.end || text.get(span.start..span.end).is_none() {
+            return None;
+        }
+
+        // An error at the end of the input points right after its last
+        // character instead of at trailing whitespace
+        let end_of_code = text.trim_end().len();
+        let (start, end) = if span.start == span.end && span.start > end_of_code {
+            (end_of_code, end_of_code)
+        } else {
+            (span.start, span.end)
+        };
+
+        let is_break = |c: char| c == '\n' || c == '\r';
+        let line_start = text[..start].rfind(is_break).map_or(0, |i| i + 1);
+        let line_end = text[start..]
+            .find(is_break)
+            .map_or(text.len(), |i| start + i);
+
+        let before = &text[line_start..start];
+        let inside = &text[start..end.min(line_end)];
+
+        Some(Self {
+            line: text[..start].matches('\n').count() + text[..start].matches('\r').count()
+                - text[..start].matches("\r\n").count()
+                + 1,
+            column: before.chars().count() + 1,
+            text: text[line_start..line_end].replace('\t', &" ".repeat(TAB_WIDTH)),
+            offset: display_width(before),
+            width: display_width(inside).max(1),
+        })
+    }
+}
+
+/// Returns the number of columns a string takes up in a snippet.
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = Source {
+            name: "main.ck",
+            text: "a = 1;\n\tb = a +;\n",
+        };
+        let diagnostic = Diagnostic::new("SyntaxError", "Expected expression", Span::new(15, 16))
+            .with_label("found \";\"")
+            .with_help("add an operand");
+
+        assert_eq!(
+            diagnostic.render(&source, false),
+            "\
+SyntaxError: Expected expression
+ --> main.ck:2:9
+  |
+2 |     b = a +;
+  |            ^ found \";\"
+  |
+  = help: add an operand
+"
+        );
+    }
+
+    #[test]
+    fn test_render_end_of_input() {
+        let source = Source {
+            name: "main.ck",
+            text: "echo \"héllo\"\n\n",
+        };
+        let diagnostic = Diagnostic::new("SyntaxError", "Expected ';'", Span::new(15, 15));
+
+        assert_eq!(
+            diagnostic.render(&source, false),
+            "\
+SyntaxError: Expected ';'
+ --> main.ck:1:13
+  |
+1 | echo \"héllo\"
+  |             ^
+"
+        );
+
+        let outside = Diagnostic::new("RuntimeError", "Oops", Span::new(40, 42));
+        assert_eq!(
+            outside.render(&source, false),
+            "RuntimeError: Oops\n--> main.ck\n"
+        );
+    }
+
+    #[test]
+    fn test_render_warning() {
+        let source = Source {
+            name: "main.ck",
+            text: "match x {}",
+        };
+        let warning = Diagnostic::warning("Match has no '_' arm", Span::new(0, 7));
+
+        assert_eq!(warning.severity(), Severity::Warning);
+        assert_eq!(
+            warning.render(&source, false),
+            "\
+Warning: Match has no '_' arm
+ --> main.ck:1:1
+  |
+1 | match x {}
+  | ^^^^^^^
+"
+        );
+    }
+}
@@ -1,55 +1,59 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fmt;
-use std::iter::zip;
-use std::time::{SystemTime, UNIX_EPOCH};
-
-mod runtime_error;
-
-use crate::internal::ast::{Expr, Stmt};
-use crate::internal::token::{Literal, Token, TokenType};
-use runtime_error::RuntimeError;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::internal::ast::{Expr, ExprKind, Param, ParamKind, Stmt, StmtKind};
+use crate::internal::runtime::{
+    arity, assertion_error, aug_assign, binary, bind_arguments, check_arity, delete_index,
+    get_index, get_value, globals, is_truthy, iterate, map_key, matches, prefix, set_index,
+    stack_overflow, unary, Environment, Map, NativeFunction, RuntimeError, StackFrame, Value,
+    MAX_CALL_DEPTH,
+};
+use crate::internal::token::{token_type, Token, TokenType};
 
 /// Chonk interpreter.
 pub struct Interpreter {
     is_interactive: bool,
     globals: Environment,
     environment: Environment,
-    retval: Option<Value>,
+    // Chonk function calls in progress, outermost first
+    calls: Vec<StackFrame>,
+}
+
+/// The control flow after executing a statement.
+enum Flow {
+    Normal,
+    Break(Option<String>),
+    Continue(Option<String>),
+    Return(Value),
 }
 
 trait Callable {
-    /// Returns the number of arguments of the function.
-    fn arity(&self) -> u8;
+    /// Returns the range of the number of arguments the function accepts.
+    fn arity(&self) -> RangeInclusive<usize>;
 
-    /// Calls the chonk function.
+    /// Calls the chonk function. `paren` is the closing parenthesis of the call
+    /// site, used for error reporting.
     fn call(
         &self,
         interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: &[Value],
     ) -> Result<Value, RuntimeError>;
 }
 
 impl Default for Interpreter {
     fn default() -> Self {
-        let mut globals = Environment::default();
-
-        globals.set(
-            "clock",
-            &Value::NativeFunction(NativeFunction {
-                name: String::from("clock"),
-                arity: 0,
-                callable: |_, _| match SystemTime::now().duration_since(UNIX_EPOCH) {
-                    Ok(n) => Ok(Value::Number(n.as_secs_f64())),
-                    Err(_) => panic!("Time went backwards!"),
-                },
-            }),
-        );
+        let globals = globals();
 
+        // The top-level scope is the global scope
         Self {
             is_interactive: false,
+            environment: globals.clone(),
             globals,
-            environment: Default::default(),
-            retval: None,
+            calls: Vec::new(),
         }
     }
 }
@@ -63,21 +67,54 @@ impl Interpreter {
         }
     }
 
-    /// Interprets a list of statements.
+    /// Interprets a list of statements. The statements must be resolved by a
+    /// `Resolver` first.
     pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        self.execute_block(statements)?;
+        Ok(())
+    }
+
+    /// Returns the names of all global variables.
+    pub fn global_names(&self) -> HashSet<String> {
+        self.globals.names()
+    }
+
+    /// Returns the value of a global variable.
+    #[cfg(test)]
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.lookup(name)
+    }
+
+    /// Returns the scope where a variable resolved to the given depth lives.
+    fn scope_of(&self, depth: &Option<usize>) -> Environment {
+        match depth {
+            Some(depth) => self.environment.ancestor(*depth),
+            None => self.globals.clone(),
+        }
+    }
+
+    /// Executes a list of statements until one of them changes the control
+    /// flow.
+    fn execute_block(&mut self, statements: &[Stmt]) -> Result<Flow, RuntimeError> {
         for stmt in statements {
-            self.execute(stmt)?;
+            match self.execute(stmt)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
         }
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    /// Looks up the variable in the current environment. If not found, check
-    /// the `globals` environment instead.
-    fn lookup(&self, name: &Token) -> Result<Value, RuntimeError> {
-        match self.environment.get(name) {
-            Ok(value) => Ok(value),
-            Err(_) => self.globals.get(name),
+    /// Assigns the default values of parameters that a call left out. The
+    /// values are computed in the scope of the call.
+    fn bind_defaults(&mut self, params: &[Param]) -> Result<(), RuntimeError> {
+        for param in params {
+            if let ParamKind::Optional(default) = &param.kind {
+                let value = self.interpret_expr(default)?;
+                self.environment.set(&param.name.lexeme, &value);
+            }
         }
+        Ok(())
     }
 
     /// Executes a list of statements in a new isolated environment.
@@ -85,93 +122,201 @@ impl Interpreter {
         &mut self,
         statements: &[Stmt],
         environment: Environment,
-    ) -> Result<(), RuntimeError> {
-        let previous = self.environment.clone();
-        self.environment = environment;
-        self.interpret(statements)?;
+    ) -> Result<Flow, RuntimeError> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let result = self.execute_block(statements);
         self.environment = previous;
-
-        Ok(())
+        result
     }
 
     /// Executes statement.
-    fn execute(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
-        if self.retval.is_some() {
-            return Ok(());
-        }
-
-        match stmt {
-            Stmt::Function { name, params, body } => {
+    fn execute(&mut self, stmt: &Stmt) -> Result<Flow, RuntimeError> {
+        match &stmt.kind {
+            StmtKind::Function { name, params, body } => {
                 let function = ChonkFunction {
-                    name: name.clone(),
-                    params: params.clone(),
-                    body: body.clone(),
+                    name: Some(name.clone()),
+                    params: Rc::clone(params),
+                    body: Rc::clone(body),
                     closure: self.environment.clone(),
                 };
                 self.environment
                     .set(&name.lexeme, &Value::ChonkFunction(function));
             }
-            Stmt::While { test, body } => {
+            StmtKind::While { label, test, body } => {
                 while is_truthy(&self.interpret_expr(test)?) {
-                    self.interpret(body)?;
+                    match self.execute_block(body)? {
+                        Flow::Break(target) if targets(&target, label) => break,
+                        Flow::Continue(target) if targets(&target, label) => continue,
+                        Flow::Normal => {}
+                        flow => return Ok(flow),
+                    }
                 }
             }
-            Stmt::If {
+            StmtKind::For {
+                label,
+                name,
+                iterable,
+                body,
+            } => {
+                let values = iterate(&self.interpret_expr(iterable)?, name)?;
+
+                for value in values {
+                    // The loop variable only lives inside the loop body
+                    let environment = Environment::new_outer(&self.environment);
+                    environment.set(&name.lexeme, &value);
+                    let result = self.execute_new(body, environment);
+
+                    match result? {
+                        Flow::Break(target) if targets(&target, label) => break,
+                        Flow::Continue(target) if targets(&target, label) => continue,
+                        Flow::Normal => {}
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            StmtKind::If {
                 test,
                 body,
                 or_else,
             } => {
                 if is_truthy(&self.interpret_expr(test)?) {
-                    self.interpret(body)?;
+                    return self.execute_block(body);
                 } else if let Some(else_stmt) = or_else {
-                    self.interpret(else_stmt)?;
+                    return self.execute_block(else_stmt);
                 }
             }
-            Stmt::Return { keyword, value } => {
-                if self.environment.outer.is_none() {
-                    return Err(RuntimeError::new(
-                        keyword.clone(),
-                        "Cannot use \"return\" outside function",
-                    ));
-                }
-
-                self.retval = Some(match value {
+            StmtKind::Break { label, .. } => {
+                return Ok(Flow::Break(label.as_ref().map(|l| l.lexeme.clone())));
+            }
+            StmtKind::Continue { label, .. } => {
+                return Ok(Flow::Continue(label.as_ref().map(|l| l.lexeme.clone())));
+            }
+            StmtKind::Return { value, .. } => {
+                return Ok(Flow::Return(match value {
                     Some(expr) => self.interpret_expr(expr)?,
                     None => Value::Null,
-                });
+                }));
+            }
+            StmtKind::Throw { keyword, value } => {
+                let value = self.interpret_expr(value)?;
+                return Err(RuntimeError::thrown(keyword, &value));
             }
-            Stmt::Delete(targets) => {
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                let mut result = self.execute_block(body);
+                if let Some(catch) = catch {
+                    if let Err(error) = result {
+                        let error = error.with_trace(&self.calls);
+                        self.scope_of(&catch.depth)
+                            .set(&catch.name.lexeme, &error.to_value());
+                        result = self.execute_block(&catch.body);
+                    }
+                }
+
+                // The finally block runs however the try statement ends, and
+                // leaving it early overrides the previous outcome
+                if let Some(finally) = finally {
+                    match self.execute_block(finally)? {
+                        Flow::Normal => {}
+                        flow => return Ok(flow),
+                    }
+                }
+                return result;
+            }
+            StmtKind::Match { subject, arms, .. } => {
+                let value = self.interpret_expr(subject)?;
+                let arm = arms
+                    .iter()
+                    .find(|arm| arm.patterns.iter().any(|p| matches(p, &value)));
+                if let Some(arm) = arm {
+                    return self.execute_block(&arm.body);
+                }
+            }
+            StmtKind::Assert {
+                keyword,
+                test,
+                source,
+                message,
+            } => {
+                // The operands of a comparison are kept to show them if it
+                // fails
+                let (token, operands) = match &test.kind {
+                    ExprKind::Binary(lhs, op, rhs) if token_type::is_comparison(op.ty) => {
+                        let left = self.interpret_expr(lhs)?;
+                        let right = self.interpret_expr(rhs)?;
+                        (op, Some((left, right)))
+                    }
+                    _ => (keyword, None),
+                };
+                let passed = match &operands {
+                    Some((left, right)) => binary(left.clone(), token, right.clone())?,
+                    None => self.interpret_expr(test)?,
+                };
+
+                if !is_truthy(&passed) {
+                    let message = match message {
+                        Some(expr) => self.interpret_expr(expr)?,
+                        None => Value::Null,
+                    };
+                    let operands = operands.as_ref().map(|(left, right)| (left, right));
+                    return Err(assertion_error(token, source, operands, &message));
+                }
+            }
+            StmtKind::Delete(targets) => {
                 for target in targets {
-                    self.environment.pop(target)?;
+                    match &target.kind {
+                        ExprKind::Variable { name, depth } => self.scope_of(depth).pop(name)?,
+                        ExprKind::Index {
+                            object,
+                            bracket,
+                            index,
+                        } => {
+                            let target = self.interpret_expr(object)?;
+                            let key = self.interpret_expr(index)?;
+                            delete_index(&target, &key, bracket)?;
+                        }
+                        _ => unreachable!("Parser only allows variables and subscripts"),
+                    }
                 }
             }
-            Stmt::Expr(expr) => {
+            StmtKind::Expr(expr) => {
                 let value = self.interpret_expr(expr)?;
                 if self.is_interactive {
                     println!("{}", value);
                 }
             }
-            Stmt::Echo(expr) => {
+            StmtKind::Echo(expr) => {
                 let value = self.interpret_expr(expr)?;
                 println!("{}", value);
             }
         }
 
-        Ok(())
+        Ok(Flow::Normal)
     }
 
     /// Interprets expression.
     fn interpret_expr(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
-        match expr {
-            Expr::Binary(lhs, op, rhs) => Ok(self.interpret_binary(lhs, op.clone(), rhs)?),
-            Expr::Unary(op, rhs) => Ok(self.interpret_unary(op.clone(), rhs)?),
-            Expr::Grouping(e) => self.interpret_expr(e),
-            Expr::Assign(name, e) => {
-                let value = self.interpret_expr(e)?;
-                self.environment.set(&name.lexeme, &value);
+        match &expr.kind {
+            ExprKind::Binary(lhs, op, rhs) => {
+                let left = self.interpret_expr(lhs)?;
+                let right = self.interpret_expr(rhs)?;
+                binary(left, op, right)
+            }
+            ExprKind::Unary(op, rhs) => {
+                let right = self.interpret_expr(rhs)?;
+                unary(op, &right)
+            }
+            ExprKind::Grouping(e) => self.interpret_expr(e),
+            ExprKind::Assign { name, value, depth } => {
+                let value = self.interpret_expr(value)?;
+                self.scope_of(depth).set(&name.lexeme, &value);
                 Ok(value)
             }
-            Expr::Logical(lhs, op, rhs) => {
+            ExprKind::Logical(lhs, op, rhs) => {
                 let left = self.interpret_expr(lhs)?;
 
                 if op.ty == TokenType::DoubleVBar {
@@ -184,76 +329,97 @@ impl Interpreter {
 
                 self.interpret_expr(rhs)
             }
-            Expr::Call(callee, paren, arguments) => self.call(callee, paren, arguments),
-            Expr::Constant(literal) => Ok(get_value(literal)),
-            Expr::Variable(name) => self.lookup(name),
-            Expr::AugAssign {
+            ExprKind::Conditional {
+                test,
+                then,
+                or_else,
+            } => {
+                if is_truthy(&self.interpret_expr(test)?) {
+                    self.interpret_expr(then)
+                } else {
+                    self.interpret_expr(or_else)
+                }
+            }
+            ExprKind::Call(callee, paren, arguments) => self.call(callee, paren, arguments),
+            ExprKind::Constant(literal) => Ok(get_value(literal)),
+            ExprKind::Variable { name, depth } => self.scope_of(depth).get(name),
+            ExprKind::Interpolation(parts) => {
+                let mut result = String::new();
+                for part in parts {
+                    result += &self.interpret_expr(part)?.to_string();
+                }
+                Ok(Value::String(result))
+            }
+            ExprKind::Lambda { params, body, .. } => Ok(Value::ChonkFunction(ChonkFunction {
+                name: None,
+                params: Rc::clone(params),
+                body: Rc::clone(body),
+                closure: self.environment.clone(),
+            })),
+            ExprKind::List(elements) => {
+                let mut values: Vec<Value> = Vec::new();
+                for element in elements {
+                    values.push(self.interpret_expr(element)?);
+                }
+                Ok(Value::List(Rc::new(RefCell::new(values))))
+            }
+            ExprKind::Map { brace, entries } => {
+                let mut map = Map::default();
+                for (key_expr, value_expr) in entries {
+                    let key = map_key(&self.interpret_expr(key_expr)?, brace)?;
+                    let value = self.interpret_expr(value_expr)?;
+                    map.insert(key, value);
+                }
+                Ok(Value::Map(Rc::new(RefCell::new(map))))
+            }
+            ExprKind::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let target = self.interpret_expr(object)?;
+                let key = self.interpret_expr(index)?;
+                get_index(&target, &key, bracket)
+            }
+            ExprKind::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let target = self.interpret_expr(object)?;
+                let key = self.interpret_expr(index)?;
+                let value = self.interpret_expr(value)?;
+                set_index(&target, &key, &value, bracket)?;
+                Ok(value)
+            }
+            ExprKind::AugAssign {
                 name,
                 operator,
                 value,
+                depth,
             } => {
-                let target = self.lookup(name)?;
-                let result = self.interpret_aug_assign(&target, operator.clone(), value)?;
-                self.environment.set(&name.lexeme, &result);
+                let scope = self.scope_of(depth);
+                let target = scope.get(name)?;
+                let rhs = self.interpret_expr(value)?;
+                let result = aug_assign(&target, operator, rhs)?;
+                scope.set(&name.lexeme, &result);
                 Ok(result)
             }
-            Expr::Prefix { operator, name } => {
-                let target = self.lookup(name)?;
-                let value = self.interpret_prefix(operator.clone(), &target)?;
-                self.environment.set(&name.lexeme, &value);
+            ExprKind::Prefix {
+                operator,
+                name,
+                depth,
+            } => {
+                let scope = self.scope_of(depth);
+                let target = scope.get(name)?;
+                let value = prefix(operator, &target)?;
+                scope.set(&name.lexeme, &value);
                 Ok(value)
             }
         }
     }
 
-    fn interpret_binary(
-        &mut self,
-        lhs: &Expr,
-        op: Token,
-        rhs: &Expr,
-    ) -> Result<Value, RuntimeError> {
-        let left = self.interpret_expr(lhs)?;
-        let right = self.interpret_expr(rhs)?;
-
-        match (left, op.ty, right) {
-            (Value::Number(n1), TokenType::Greater, Value::Number(n2)) => Ok(Value::Bool(n1 > n2)),
-            (Value::Number(n1), TokenType::GreaterEqual, Value::Number(n2)) => {
-                Ok(Value::Bool(n1 >= n2))
-            }
-            (Value::Number(n1), TokenType::Less, Value::Number(n2)) => Ok(Value::Bool(n1 < n2)),
-            (Value::Number(n1), TokenType::LessEqual, Value::Number(n2)) => {
-                Ok(Value::Bool(n1 <= n2))
-            }
-            (Value::Number(n1), TokenType::BangEqual, Value::Number(n2)) => {
-                Ok(Value::Bool(n1 != n2))
-            }
-            (Value::Number(n1), TokenType::EqEqual, Value::Number(n2)) => Ok(Value::Bool(n1 == n2)),
-            (Value::Number(n1), TokenType::Minus, Value::Number(n2)) => Ok(Value::Number(n1 - n2)),
-            (Value::Number(n1), TokenType::Plus, Value::Number(n2)) => Ok(Value::Number(n1 + n2)),
-            (Value::String(s1), TokenType::Plus, Value::String(s2)) => Ok(Value::String(s1 + &s2)),
-            (Value::Number(n1), TokenType::Percent, Value::Number(n2)) => {
-                Ok(Value::Number(n1 % n2))
-            }
-            (Value::Number(n1), TokenType::Slash, Value::Number(n2)) => Ok(Value::Number(n1 / n2)),
-            (Value::Number(n1), TokenType::Star, Value::Number(n2)) => Ok(Value::Number(n1 * n2)),
-            _ => Err(RuntimeError::new(op, "Invalid operands in binary operator")),
-        }
-    }
-
-    fn interpret_unary(&mut self, op: Token, rhs: &Expr) -> Result<Value, RuntimeError> {
-        let right = &self.interpret_expr(rhs)?;
-
-        match (op.ty, right) {
-            (TokenType::Plus, Value::Number(value)) => Ok(Value::Number(*value)),
-            (TokenType::Minus, Value::Number(value)) => Ok(Value::Number(-value)),
-            (TokenType::Bang, _) => match is_truthy(right) {
-                true => Ok(Value::Bool(false)),
-                false => Ok(Value::Bool(true)),
-            },
-            _ => Err(RuntimeError::new(op, "Invalid operand to unary operator")),
-        }
-    }
-
     fn call(
         &mut self,
         callee: &Expr,
@@ -273,106 +439,19 @@ impl Interpreter {
             return Err(RuntimeError::new(paren.clone(), "Can only call functions"));
         };
 
-        if args.len() != function.arity().into() {
-            return Err(RuntimeError::new(
-                paren.clone(),
-                &format!(
-                    "Expected {} arguments but got {}",
-                    function.arity(),
-                    args.len()
-                ),
-            ));
-        }
-
-        function.call(self, &args)
-    }
-
-    fn interpret_aug_assign(
-        &mut self,
-        target: &Value,
-        operator: Token,
-        value: &Expr,
-    ) -> Result<Value, RuntimeError> {
-        let rhs = self.interpret_expr(value)?;
-
-        match (target, operator.ty, rhs) {
-            (Value::Number(n1), TokenType::MinusEqual, Value::Number(n2)) => {
-                Ok(Value::Number(n1 - n2))
-            }
-            (Value::Number(n1), TokenType::PlusEqual, Value::Number(n2)) => {
-                Ok(Value::Number(n1 + n2))
-            }
-            (Value::String(s1), TokenType::PlusEqual, Value::String(s2)) => {
-                Ok(Value::String(s1.to_owned() + &s2))
-            }
-            (Value::Number(n1), TokenType::PercentEqual, Value::Number(n2)) => {
-                Ok(Value::Number(n1 % n2))
-            }
-            (Value::Number(n1), TokenType::SlashEqual, Value::Number(n2)) => {
-                Ok(Value::Number(n1 / n2))
-            }
-            (Value::Number(n1), TokenType::StarEqual, Value::Number(n2)) => {
-                Ok(Value::Number(n1 * n2))
-            }
-            _ => Err(RuntimeError::new(
-                operator,
-                "Invalid value in assignment operator",
-            )),
-        }
-    }
-
-    fn interpret_prefix(&mut self, operator: Token, target: &Value) -> Result<Value, RuntimeError> {
-        match (operator.ty, target) {
-            (TokenType::DoubleMinus, Value::Number(n)) => Ok(Value::Number(n - 1.0)),
-            (TokenType::DoublePlus, Value::Number(n)) => Ok(Value::Number(n + 1.0)),
-            _ => Err(RuntimeError::new(
-                operator,
-                "Invalid value to prefix operator",
-            )),
-        }
-    }
-}
-
-/// Returns `true` if the value is "truthy".
-fn is_truthy(value: &Value) -> bool {
-    match value {
-        Value::Null => false,
-        Value::Bool(value) => *value,
-        _ => true,
+        check_arity(function.arity(), args.len(), paren)?;
+        function.call(self, paren, &args)
     }
 }
 
-/// Returns value from literal.
-fn get_value(literal: &Literal) -> Value {
-    match literal {
-        Literal::Number(n) => Value::Number(*n),
-        Literal::String(s) => Value::String(s.to_owned()),
-        Literal::True => Value::Bool(true),
-        Literal::False => Value::Bool(false),
-        Literal::Null => Value::Null,
-    }
-}
-
-#[derive(Clone)]
-enum Value {
-    Number(f64),
-    String(String),
-    Bool(bool),
-    NativeFunction(NativeFunction),
-    ChonkFunction(ChonkFunction),
-    Null,
-}
-
-impl fmt::Display for Value {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Number(value) => write!(f, "{value}"),
-            Value::String(value) => write!(f, "{value}"),
-            Value::Bool(value) => write!(f, "{value}"),
-            Value::NativeFunction(func) => write!(f, "{func}"),
-            Value::ChonkFunction(func) => write!(f, "{func}"),
-            Value::Null => write!(f, "null"),
-        }
+/// Returns `true` if a `break` or `continue` with the target label applies to
+/// the loop with the given label. Statements without a label target the
+/// innermost loop.
+fn targets(target: &Option<String>, label: &Option<Token>) -> bool {
+    match (target, label) {
+        (None, _) => true,
+        (Some(target), Some(label)) => *target == label.lexeme,
+        (Some(_), None) => false,
     }
 }
 
@@ -386,132 +465,97 @@ impl Value {
     }
 }
 
-#[derive(Default, Clone)]
-struct Environment {
-    store: HashMap<String, Value>,
-    outer: Option<Box<Environment>>,
-}
-
-impl Environment {
-    /// Creates a new outer scope.
-    fn new_outer(outer: Environment) -> Self {
-        Self {
-            outer: Some(Box::new(outer)),
-            ..Default::default()
-        }
-    }
-
-    /// Returns the value bound to the name.
-    fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
-        if let Some(value) = self.store.get(&name.lexeme) {
-            return Ok(value.clone());
-        }
-
-        if let Some(outer_env) = &self.outer {
-            return outer_env.get(name);
-        }
-
-        Err(RuntimeError::new(
-            name.clone(),
-            &format!("Undefined variable \"{}\"", name.lexeme),
-        ))
-    }
-
-    /// Binds a new name to a value. If the name exists, it assigns a new value
-    /// to it.
-    fn set(&mut self, name: &str, value: &Value) {
-        self.store.insert(name.to_string(), value.clone());
-    }
-
-    /// Removes a name-value pair.
-    fn pop(&mut self, name: &Token) -> Result<(), RuntimeError> {
-        if self.store.remove(&name.lexeme).is_none() {
-            if let Some(ref mut outer_env) = self.outer {
-                outer_env.pop(name)?;
-            }
-
-            return Err(RuntimeError::new(
-                name.clone(),
-                &format!("Undefined variable \"{}\"", name.lexeme),
-            ));
-        }
-
-        Ok(())
-    }
-}
-
-#[derive(Clone)]
-struct NativeFunction {
-    name: String,
-    arity: u8,
-    callable: fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError>,
-}
-
-impl fmt::Display for NativeFunction {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<native function {}>", self.name)
-    }
-}
-
 impl Callable for NativeFunction {
-    fn arity(&self) -> u8 {
-        self.arity
+    fn arity(&self) -> RangeInclusive<usize> {
+        self.arity.clone()
     }
 
     fn call(
         &self,
-        interpreter: &mut Interpreter,
+        _interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: &[Value],
     ) -> Result<Value, RuntimeError> {
-        (self.callable)(interpreter, arguments)
+        (self.callable)(paren, arguments)
     }
 }
 
 #[derive(Clone)]
-struct ChonkFunction {
-    name: Token,
-    params: Vec<Token>,
-    body: Vec<Stmt>,
+pub struct ChonkFunction {
+    // Anonymous functions have no name
+    name: Option<Token>,
+    params: Rc<Vec<Param>>,
+    body: Rc<Vec<Stmt>>,
     closure: Environment,
 }
 
 impl fmt::Display for ChonkFunction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<function {}>", self.name.lexeme)
+        match &self.name {
+            Some(name) => write!(f, "<function {}>", name.lexeme),
+            None => write!(f, "<anonymous function>"),
+        }
+    }
+}
+
+impl PartialEq for ChonkFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.body, &other.body) && self.closure.ptr_eq(&other.closure)
     }
 }
 
 impl Callable for ChonkFunction {
-    fn arity(&self) -> u8 {
-        self.params.len().try_into().unwrap()
+    fn arity(&self) -> RangeInclusive<usize> {
+        arity(&self.params)
     }
 
     fn call(
         &self,
         interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: &[Value],
     ) -> Result<Value, RuntimeError> {
-        let mut environment = Environment::new_outer(self.closure.clone());
-        for (param, arg) in zip(&self.params, arguments) {
-            environment.set(&param.lexeme, arg);
+        if interpreter.calls.len() >= MAX_CALL_DEPTH {
+            return Err(stack_overflow(paren));
         }
 
-        let saved_retval = interpreter.retval.clone();
-        interpreter.execute_new(&self.body, environment)?;
-        let retval = interpreter.retval.clone();
-        interpreter.retval = saved_retval;
-
-        match retval {
-            Some(value) => Ok(value),
-            None => Ok(Value::Null),
+        let environment = Environment::new_outer(&self.closure);
+        let bound = bind_arguments(&self.params, arguments, &environment);
+
+        interpreter
+            .calls
+            .push(StackFrame::new(self.name.as_ref(), paren));
+        let previous = std::mem::replace(&mut interpreter.environment, environment);
+        let flow = interpreter
+            .bind_defaults(&self.params[bound..])
+            .and_then(|()| interpreter.execute_block(&self.body))
+            .map_err(|error| error.with_trace(&interpreter.calls));
+        interpreter.environment = previous;
+        interpreter.calls.pop();
+
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Value::Null),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::thread;
+
     use super::*;
     use crate::internal::parser::Parser;
+    use crate::internal::resolver::Resolver;
+    use crate::internal::runtime::STACK_SIZE;
+
+    /// Parses and resolves the input.
+    fn parse(interpreter: &Interpreter, input: &str) -> Vec<Stmt> {
+        let mut statements = Parser::new(input).parse().unwrap();
+        let mut resolver = Resolver::new(interpreter.global_names());
+        resolver.resolve(&mut statements).unwrap();
+        statements
+    }
 
     #[test]
     fn test_interpret() -> Result<(), RuntimeError> {
@@ -528,11 +572,174 @@ mod tests {
             del result;
         ";
 
-        let mut parser = Parser::new(input);
-        let statements = parser.parse().unwrap();
-
         let mut interpreter = Interpreter::new(false);
+        let statements = parse(&interpreter, input);
         assert!(interpreter.interpret(&statements).is_ok());
         Ok(())
     }
+
+    #[test]
+    fn test_list_index() {
+        let mut interpreter = Interpreter::new(false);
+        let statements = parse(&interpreter, "xs = [1, 2, 3]; xs[-1] = xs[0]; echo xs;");
+        assert!(interpreter.interpret(&statements).is_ok());
+
+        let statements = parse(&interpreter, "xs[3];");
+        assert!(interpreter.interpret(&statements).is_err());
+    }
+
+    #[test]
+    fn test_for_loop() {
+        let input = "\
+            total = 0;
+            for i in range(1, 10, 2) {
+                total += i;
+            }
+            for c in \"abc\" {
+                total += len(c);
+                last = c;
+            }
+        ";
+
+        let mut interpreter = Interpreter::new(false);
+        let statements = parse(&interpreter, input);
+        assert!(interpreter.interpret(&statements).is_ok());
+        assert!(interpreter.global("total") == Some(Value::Number(28.0)));
+        assert!(interpreter.global("i").is_none());
+        assert!(interpreter.global("last") == Some(Value::String(String::from("c"))));
+    }
+
+    #[test]
+    fn test_lambda() {
+        let input = "\
+            func apply(f, x) {
+                return f(x);
+            }
+
+            double = apply((x) => x * 2, 4);
+            square = apply(func(x) { return x * x; }, 4);
+        ";
+
+        let mut interpreter = Interpreter::new(false);
+        let statements = parse(&interpreter, input);
+        assert!(interpreter.interpret(&statements).is_ok());
+        assert!(interpreter.global("double") == Some(Value::Number(8.0)));
+        assert!(interpreter.global("square") == Some(Value::Number(16.0)));
+    }
+
+    #[test]
+    fn test_closure() {
+        let input = "\
+            func make() {
+                n = 0;
+                func inc() {
+                    n += 1;
+                    return n;
+                }
+                return inc;
+            }
+
+            counter = make();
+            counter();
+            result = counter();
+        ";
+
+        let mut interpreter = Interpreter::new(false);
+        let statements = parse(&interpreter, input);
+        assert!(interpreter.interpret(&statements).is_ok());
+        assert!(interpreter.global("result") == Some(Value::Number(2.0)));
+    }
+
+    #[test]
+    fn test_try() {
+        let input = "\
+            func fail() {
+                len(1);
+            }
+
+            log = [];
+            try {
+                fail();
+            } catch e {
+                error = [e[\"kind\"], e[\"line\"]];
+            } finally {
+                log = log + [\"finally\"];
+            }
+        ";
+
+        let mut interpreter = Interpreter::new(false);
+        let statements = parse(&interpreter, input);
+        assert!(interpreter.interpret(&statements).is_ok());
+        assert_eq!(
+            interpreter.global("error").map(|value| value.to_string()),
+            Some(String::from("[\"RuntimeError\", 2]"))
+        );
+        assert_eq!(
+            interpreter.global("log").map(|value| value.to_string()),
+            Some(String::from("[\"finally\"]"))
+        );
+
+        let input = "\
+            try {
+                try { throw {\"code\": 404}; } catch e { throw e; }
+            } catch e {
+                thrown = e[\"value\"][\"code\"];
+            }
+            try { assert false; } catch e { thrown = [thrown, e[\"kind\"], e[\"value\"]]; }
+        ";
+        let statements = parse(&interpreter, input);
+        assert!(interpreter.interpret(&statements).is_ok());
+        assert_eq!(
+            interpreter.global("thrown").map(|value| value.to_string()),
+            Some(String::from("[404, \"AssertionError\", null]"))
+        );
+
+        let statements = parse(&interpreter, "try { throw 1; } finally { log = []; }");
+        assert!(interpreter.interpret(&statements).is_err());
+        assert_eq!(
+            interpreter.global("log").map(|value| value.to_string()),
+            Some(String::from("[]"))
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        // A test thread does not have enough stack to reach the call limit
+        let thread = thread::Builder::new().stack_size(STACK_SIZE).spawn(|| {
+            let mut interpreter = Interpreter::new(false);
+            let program = "func f(n) { return f(n + 1) + 1; }
+                try { f(0); } catch e { result = e[\"message\"]; }";
+            let statements = parse(&interpreter, program);
+            assert!(interpreter.interpret(&statements).is_ok());
+            assert!(
+                interpreter.global("result") == Some(Value::String(String::from("Stack overflow")))
+            );
+            assert!(interpreter.calls.is_empty());
+        });
+        thread.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_shared_function_body() {
+        let input = "\
+            func make() {
+                return func() { return 1; };
+            }
+
+            f = make();
+            g = make();
+        ";
+
+        let mut interpreter = Interpreter::new(false);
+        let statements = parse(&interpreter, input);
+        assert!(interpreter.interpret(&statements).is_ok());
+
+        let (Some(Value::ChonkFunction(f)), Some(Value::ChonkFunction(g))) =
+            (interpreter.global("f"), interpreter.global("g"))
+        else {
+            panic!("Expected functions");
+        };
+        assert!(Rc::ptr_eq(&f.body, &g.body));
+        assert!(f != g);
+    }
 }
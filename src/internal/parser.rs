@@ -1,45 +1,149 @@
-mod error_reporter;
+mod lex_error;
 mod lexer;
 mod parse_error;
 
-use crate::internal::ast::{Expr, Stmt};
-use crate::internal::token::{token_type, Literal, Token, TokenType};
-use error_reporter::ErrorReporter;
+use std::rc::Rc;
+
+use crate::internal::ast::{
+    Catch, Expr, ExprKind, MatchArm, Param, ParamKind, Pattern, PatternKind, Stmt, StmtKind,
+};
+use crate::internal::diagnostic::Diagnostic;
+use crate::internal::token::{token_type, Literal, Span, Token, TokenType};
 use lexer::Lexer;
 use parse_error::ParseError;
 
 /// A parser for Chonk source code.
 #[derive(Default)]
 pub struct Parser {
+    source: String,
+    // Byte offset of the source in the whole input
+    offset: usize,
     tokens: Vec<Token>,
     current: usize,
+    // Labels of the loops enclosing the current statement
+    loops: Vec<Option<String>>,
+    // Errors found so far, starting with the ones found while scanning
+    errors: Vec<ParseError>,
+    warnings: Vec<Diagnostic>,
 }
 
 impl Parser {
     /// Creates a new `Parser`.
     pub fn new(input: &str) -> Self {
-        let mut lexer = Lexer::new(input);
+        Self::starting_at(input, 0, 1)
+    }
+
+    /// Creates a new `Parser` for a part of a larger input, which starts a
+    /// line at the given byte offset and line number. The syntax tree keeps
+    /// the position of the code in the whole input.
+    pub fn starting_at(input: &str, offset: usize, line: usize) -> Self {
+        let mut lexer = Lexer::starting_at(input, offset, line);
         let tokens = lexer.scan_tokens().to_vec();
 
         Self {
+            source: input.to_string(),
+            offset,
             tokens,
+            errors: lexer
+                .errors()
+                .iter()
+                .cloned()
+                .map(ParseError::Lex)
+                .collect(),
             ..Default::default()
         }
     }
 
-    /// Parses statements.
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParseError> {
+    /// Parses statements. Fails with every lexical and syntax error if there
+    /// are any.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        let (statements, errors) = self.parse_partial();
+        if errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses statements, skipping the ones with syntax errors. Returns the
+    /// statements that could be parsed together with every error.
+    pub fn parse_partial(&mut self) -> (Vec<Stmt>, Vec<ParseError>) {
         let mut statements: Vec<Stmt> = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.statement()?);
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
         }
 
-        Ok(statements)
+        (statements, std::mem::take(&mut self.errors))
+    }
+
+    /// Returns the warnings about code that was parsed.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Parses a statement. On a syntax error, records it and skips to the
+    /// start of the next statement.
+    fn declaration(&mut self) -> Option<Stmt> {
+        let start = self.current;
+        match self.statement() {
+            Ok(stmt) => Some(stmt),
+            Err(error) => {
+                self.report(error);
+                // Always make progress, even if the statement failed on its
+                // first token
+                if self.current == start {
+                    self.advance();
+                }
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    /// Skips tokens until a statement boundary: after a semicolon, or before
+    /// a keyword that starts a statement or a closing brace.
+    fn synchronize(&mut self) {
+        while !self.is_at_end() {
+            if self.previous().ty == TokenType::Semicolon {
+                return;
+            }
+
+            match self.peek().ty {
+                TokenType::Func
+                | TokenType::If
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Return
+                | TokenType::Echo
+                | TokenType::Try
+                | TokenType::Throw
+                | TokenType::Assert
+                | TokenType::Match
+                | TokenType::RBrace => return,
+                _ => {}
+            }
+
+            self.advance();
+        }
     }
 
-    /// Parses statements.
+    /// Parses statement.
     fn statement(&mut self) -> Result<Stmt, ParseError> {
-        if self.match_type(TokenType::Func) {
+        let start: Span = self.peek().span;
+        let kind = self.statement_kind()?;
+        Ok(Stmt::new(kind, start.to(self.previous().span)))
+    }
+
+    /// Parses the kind of statement that starts at the current token.
+    fn statement_kind(&mut self) -> Result<StmtKind, ParseError> {
+        if self.has_type(TokenType::Ident) && self.peek_next().ty == TokenType::Colon {
+            return self.labeled_statement();
+        }
+        // A function without a name is an expression
+        if self.has_type(TokenType::Func) && self.peek_next().ty == TokenType::Ident {
+            self.advance();
             return self.function_statement();
         }
         if self.match_type(TokenType::Return) {
@@ -48,8 +152,14 @@ impl Parser {
         if self.match_type(TokenType::Delete) {
             return self.delete_statement();
         }
+        if self.match_types(&[TokenType::Break, TokenType::Continue]) {
+            return self.loop_control_statement();
+        }
         if self.match_type(TokenType::While) {
-            return self.while_statement();
+            return self.while_statement(None);
+        }
+        if self.match_type(TokenType::For) {
+            return self.for_statement(None);
         }
         if self.match_type(TokenType::If) {
             return self.if_statement();
@@ -57,23 +167,80 @@ impl Parser {
         if self.match_type(TokenType::Echo) {
             return self.echo_statement();
         }
+        if self.match_type(TokenType::Throw) {
+            return self.throw_statement();
+        }
+        if self.match_type(TokenType::Try) {
+            return self.try_statement();
+        }
+        if self.match_type(TokenType::Assert) {
+            return self.assert_statement();
+        }
+        if self.match_type(TokenType::Match) {
+            return self.match_statement();
+        }
 
         self.expression_statement()
     }
 
     /// Parses function definition statement.
-    fn function_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn function_statement(&mut self) -> Result<StmtKind, ParseError> {
         let name: Token = self.consume(TokenType::Ident, "Expected function name")?;
         self.consume(TokenType::LParen, "Expected '(' after function name")?;
-        let mut params: Vec<Token> = Vec::new();
+        let params: Vec<Param> = self.parameters()?;
+        let body: Vec<Stmt> = self.function_body()?;
+
+        Ok(StmtKind::Function {
+            name,
+            params: Rc::new(params),
+            body: Rc::new(body),
+        })
+    }
+
+    /// Parses the parameters of a function up to and including the closing
+    /// parenthesis. Parameters with default values follow the required ones,
+    /// and a rest parameter comes last.
+    fn parameters(&mut self) -> Result<Vec<Param>, ParseError> {
+        let mut params: Vec<Param> = Vec::new();
 
         if !self.has_type(TokenType::RParen) {
             loop {
                 if params.len() >= 255 {
-                    self.token_error(self.peek(), "Can't have more than 255 parameters");
+                    self.report(ParseError::InvalidStatement {
+                        found: self.peek().clone(),
+                        message: String::from("Can't have more than 255 parameters"),
+                    });
                 }
 
-                params.push(self.consume(TokenType::Ident, "Expected parameter name")?);
+                if self.match_type(TokenType::Ellipsis) {
+                    let name: Token =
+                        self.consume(TokenType::Ident, "Expected parameter name after '...'")?;
+                    params.push(Param {
+                        name,
+                        kind: ParamKind::Rest,
+                    });
+                    self.consume(TokenType::RParen, "Expected ')' after rest parameter")?;
+                    return Ok(params);
+                }
+
+                let name: Token = self.consume(TokenType::Ident, "Expected parameter name")?;
+                let kind = if self.match_type(TokenType::Equal) {
+                    ParamKind::Optional(Box::new(self.expression()?))
+                } else {
+                    if params
+                        .iter()
+                        .any(|p| matches!(p.kind, ParamKind::Optional(_)))
+                    {
+                        self.report(ParseError::InvalidStatement {
+                            found: name.clone(),
+                            message: String::from(
+                                "Required parameter cannot follow a parameter with a default value",
+                            ),
+                        });
+                    }
+                    ParamKind::Required
+                };
+                params.push(Param { name, kind });
 
                 if !self.match_type(TokenType::Comma) {
                     break;
@@ -82,13 +249,20 @@ impl Parser {
         }
 
         self.consume(TokenType::RParen, "Expected ')' after parameters")?;
-        let body: Vec<Stmt> = self.block()?;
+        Ok(params)
+    }
 
-        Ok(Stmt::Function { name, params, body })
+    /// Parses the block of a function.
+    fn function_body(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        // Loops outside the function cannot be controlled from inside it
+        let loops = std::mem::take(&mut self.loops);
+        let body = self.block();
+        self.loops = loops;
+        body
     }
 
     /// Parses return statement.
-    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn return_statement(&mut self) -> Result<StmtKind, ParseError> {
         let keyword: Token = self.previous().clone();
         let value = if !self.has_type(TokenType::Semicolon) {
             Some(self.expression()?)
@@ -97,14 +271,19 @@ impl Parser {
         };
 
         self.consume(TokenType::Semicolon, "Expected ';' after return value")?;
-        Ok(Stmt::Return { keyword, value })
+        Ok(StmtKind::Return { keyword, value })
     }
 
     /// Parses delete statement.
-    fn delete_statement(&mut self) -> Result<Stmt, ParseError> {
-        let mut targets: Vec<Token> = Vec::new();
+    fn delete_statement(&mut self) -> Result<StmtKind, ParseError> {
+        let keyword: Token = self.previous().clone();
+        let mut targets: Vec<Expr> = Vec::new();
         loop {
-            targets.push(self.consume(TokenType::Ident, "Expected variable name")?);
+            let target = self.call()?;
+            match target.kind {
+                ExprKind::Variable { .. } | ExprKind::Index { .. } => targets.push(target),
+                _ => self.invalid_target(target.span, &keyword, "Invalid delete target"),
+            }
 
             if !self.match_type(TokenType::Comma) {
                 break;
@@ -112,18 +291,94 @@ impl Parser {
         }
 
         self.consume(TokenType::Semicolon, "Expected ';' after del statement")?;
-        Ok(Stmt::Delete(targets))
+        Ok(StmtKind::Delete(targets))
+    }
+
+    /// Parses a loop statement preceded by a label.
+    fn labeled_statement(&mut self) -> Result<StmtKind, ParseError> {
+        let label: Token = self.advance().clone();
+        self.advance();
+
+        if self.match_type(TokenType::While) {
+            return self.while_statement(Some(label));
+        }
+        if self.match_type(TokenType::For) {
+            return self.for_statement(Some(label));
+        }
+
+        Err(ParseError::InvalidStatement {
+            found: self.peek().clone(),
+            message: String::from("Expected loop after label"),
+        })
+    }
+
+    /// Parses break or continue statement.
+    fn loop_control_statement(&mut self) -> Result<StmtKind, ParseError> {
+        let keyword: Token = self.previous().clone();
+        let label = if self.match_type(TokenType::Ident) {
+            Some(self.previous().clone())
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::Semicolon,
+            &format!("Expected ';' after {} statement", keyword.lexeme),
+        )?;
+
+        if self.loops.is_empty() {
+            return Err(ParseError::InvalidStatement {
+                message: format!("Cannot use \"{}\" outside loop", keyword.lexeme),
+                found: keyword,
+            });
+        }
+        if let Some(label) = &label {
+            if !self.loops.contains(&Some(label.lexeme.clone())) {
+                return Err(ParseError::InvalidStatement {
+                    found: label.clone(),
+                    message: format!("Undefined loop label \"{}\"", label.lexeme),
+                });
+            }
+        }
+
+        if keyword.ty == TokenType::Break {
+            Ok(StmtKind::Break { keyword, label })
+        } else {
+            Ok(StmtKind::Continue { keyword, label })
+        }
     }
 
     /// Parses while statement.
-    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn while_statement(&mut self, label: Option<Token>) -> Result<StmtKind, ParseError> {
         let test = self.expression()?;
-        let body: Vec<Stmt> = self.block()?;
-        Ok(Stmt::While { test, body })
+        let body: Vec<Stmt> = self.loop_body(&label)?;
+        Ok(StmtKind::While { label, test, body })
+    }
+
+    /// Parses for statement.
+    fn for_statement(&mut self, label: Option<Token>) -> Result<StmtKind, ParseError> {
+        let name: Token = self.consume(TokenType::Ident, "Expected variable name after 'for'")?;
+        self.consume(TokenType::In, "Expected 'in' after loop variable")?;
+        let iterable = self.expression()?;
+        let body: Vec<Stmt> = self.loop_body(&label)?;
+        Ok(StmtKind::For {
+            label,
+            name,
+            iterable,
+            body,
+        })
+    }
+
+    /// Parses the block of a loop.
+    fn loop_body(&mut self, label: &Option<Token>) -> Result<Vec<Stmt>, ParseError> {
+        self.loops.push(label.as_ref().map(|l| l.lexeme.clone()));
+        let body = self.block();
+        self.loops.pop();
+        body
     }
 
     /// Parses if statement.
-    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn if_statement(&mut self) -> Result<StmtKind, ParseError> {
         let test = self.expression()?;
 
         let body: Vec<Stmt> = self.block()?;
@@ -133,7 +388,7 @@ impl Parser {
             None
         };
 
-        Ok(Stmt::If {
+        Ok(StmtKind::If {
             test,
             body,
             or_else,
@@ -141,17 +396,195 @@ impl Parser {
     }
 
     /// Parses expression statement.
-    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn expression_statement(&mut self) -> Result<StmtKind, ParseError> {
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon, "Expected ';' after expression")?;
-        Ok(Stmt::Expr(expr))
+        Ok(StmtKind::Expr(expr))
     }
 
     /// Parses echo statement.
-    fn echo_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn echo_statement(&mut self) -> Result<StmtKind, ParseError> {
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expected ';' after value")?;
-        Ok(Stmt::Echo(value))
+        Ok(StmtKind::Echo(value))
+    }
+
+    /// Parses throw statement.
+    fn throw_statement(&mut self) -> Result<StmtKind, ParseError> {
+        let keyword: Token = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expected ';' after thrown value")?;
+        Ok(StmtKind::Throw { keyword, value })
+    }
+
+    /// Parses assert statement with an optional message.
+    fn assert_statement(&mut self) -> Result<StmtKind, ParseError> {
+        let keyword: Token = self.previous().clone();
+        let test = self.expression()?;
+        let source = self
+            .source
+            .get(test.span.start - self.offset..test.span.end - self.offset)
+            .unwrap_or_default()
+            .to_string();
+
+        let message = if self.match_type(TokenType::Comma) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expected ';' after assertion")?;
+
+        Ok(StmtKind::Assert {
+            keyword,
+            test,
+            source,
+            message,
+        })
+    }
+
+    /// Parses match statement.
+    fn match_statement(&mut self) -> Result<StmtKind, ParseError> {
+        let keyword: Token = self.previous().clone();
+        let subject = self.expression()?;
+        self.consume(TokenType::LBrace, "Expected '{' after match value")?;
+
+        let mut arms: Vec<MatchArm> = Vec::new();
+        while !self.has_type(TokenType::RBrace) && !self.is_at_end() {
+            arms.push(self.match_arm()?);
+        }
+        self.consume(TokenType::RBrace, "Expected '}' after match arms")?;
+
+        let has_wildcard = arms
+            .iter()
+            .flat_map(|arm| &arm.patterns)
+            .any(|pattern| pattern.kind == PatternKind::Wildcard);
+        if !has_wildcard {
+            self.warnings.push(
+                Diagnostic::warning("Match has no '_' arm", keyword.span.to(subject.span))
+                    .with_label("values matching no pattern are ignored")
+                    .with_help("add '_ => {}' to handle every other value"),
+            );
+        }
+
+        Ok(StmtKind::Match {
+            keyword,
+            subject,
+            arms,
+        })
+    }
+
+    /// Parses an arm of a match statement. Its body is either a block, which
+    /// may be followed by a comma, or a single statement.
+    fn match_arm(&mut self) -> Result<MatchArm, ParseError> {
+        let mut patterns: Vec<Pattern> = vec![self.pattern()?];
+        while self.match_type(TokenType::VBar) {
+            patterns.push(self.pattern()?);
+        }
+        self.consume(TokenType::Arrow, "Expected '=>' after pattern")?;
+
+        let body: Vec<Stmt> = if self.has_type(TokenType::LBrace) {
+            let body = self.block()?;
+            self.match_type(TokenType::Comma);
+            body
+        } else {
+            vec![self.statement()?]
+        };
+
+        Ok(MatchArm { patterns, body })
+    }
+
+    /// Parses a pattern of a match arm.
+    fn pattern(&mut self) -> Result<Pattern, ParseError> {
+        let start: Span = self.peek().span;
+        if self.has_type(TokenType::Ident) && self.peek().lexeme == "_" {
+            self.advance();
+            return Ok(Pattern {
+                kind: PatternKind::Wildcard,
+                span: start,
+            });
+        }
+
+        let literal = self.pattern_literal()?;
+        let kind = if self.match_type(TokenType::DotDot) {
+            let dots: Token = self.previous().clone();
+            let end = self.pattern_literal()?;
+            match (&literal, &end) {
+                (Literal::Number(_), Literal::Number(_))
+                | (Literal::String(_), Literal::String(_)) => {}
+                _ => self.report(ParseError::InvalidStatement {
+                    found: dots,
+                    message: String::from("Range bounds must both be numbers or both be strings"),
+                }),
+            }
+            PatternKind::Range {
+                start: literal,
+                end,
+            }
+        } else {
+            PatternKind::Literal(literal)
+        };
+
+        Ok(Pattern {
+            kind,
+            span: start.to(self.previous().span),
+        })
+    }
+
+    /// Parses a literal in a pattern, which may be a negative number.
+    fn pattern_literal(&mut self) -> Result<Literal, ParseError> {
+        let is_negative = self.match_type(TokenType::Minus);
+        let token: Token = self.peek().clone();
+
+        let literal = match (token.ty, &token.literal) {
+            (TokenType::Number, Some(Literal::Number(n))) => {
+                Literal::Number(if is_negative { -n } else { *n })
+            }
+            _ if is_negative => return Err(ParseError::ExpectedPattern(token)),
+            (TokenType::String, Some(literal)) => literal.clone(),
+            (TokenType::True, _) => Literal::True,
+            (TokenType::False, _) => Literal::False,
+            (TokenType::Null, _) => Literal::Null,
+            _ => return Err(ParseError::ExpectedPattern(token)),
+        };
+        self.advance();
+        Ok(literal)
+    }
+
+    /// Parses try statement with a catch clause, a finally clause or both.
+    fn try_statement(&mut self) -> Result<StmtKind, ParseError> {
+        let keyword: Token = self.previous().clone();
+        let body: Vec<Stmt> = self.block()?;
+
+        let catch = if self.match_type(TokenType::Catch) {
+            let name: Token =
+                self.consume(TokenType::Ident, "Expected variable name after 'catch'")?;
+            Some(Catch {
+                name,
+                depth: None,
+                body: self.block()?,
+            })
+        } else {
+            None
+        };
+        let finally = if self.match_type(TokenType::Finally) {
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(self.mismatch(
+                TokenType::Catch,
+                "Expected 'catch' or 'finally' after try block",
+            ));
+        }
+
+        Ok(StmtKind::Try {
+            keyword,
+            body,
+            catch,
+            finally,
+        })
     }
 
     /// Parses a block of statements.
@@ -160,7 +593,9 @@ impl Parser {
         let mut statements: Vec<Stmt> = Vec::new();
 
         while !self.has_type(TokenType::RBrace) && !self.is_at_end() {
-            statements.push(self.statement()?);
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
         }
 
         self.consume(TokenType::RBrace, "Expected '}' after block")?;
@@ -180,11 +615,30 @@ impl Parser {
             let equals: Token = self.previous().clone();
             let value: Expr = self.assignment()?;
 
-            if let Expr::Variable(name) = expr {
-                return Ok(Expr::Assign(name, Box::new(value)));
+            match expr.kind {
+                ExprKind::Variable { name, .. } => {
+                    let kind = ExprKind::Assign {
+                        name,
+                        value: Box::new(value),
+                        depth: None,
+                    };
+                    return Ok(self.node(kind, expr.span));
+                }
+                ExprKind::Index {
+                    object,
+                    bracket,
+                    index,
+                } => {
+                    let kind = ExprKind::SetIndex {
+                        object,
+                        bracket,
+                        index,
+                        value: Box::new(value),
+                    };
+                    return Ok(self.node(kind, expr.span));
+                }
+                _ => self.invalid_target(expr.span, &equals, "Invalid assignment target"),
             }
-
-            self.token_error(&equals, "Invalid assignment target");
         }
 
         Ok(expr)
@@ -192,7 +646,7 @@ impl Parser {
 
     /// Parses augmented assignment expression.
     fn aug_assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.or()?;
+        let expr = self.conditional()?;
 
         if self.match_types(&[
             TokenType::MinusEqual,
@@ -202,30 +656,72 @@ impl Parser {
             TokenType::StarEqual,
         ]) {
             let operator: Token = self.previous().clone();
-            let value: Expr = self.or()?;
+            let value: Expr = self.conditional()?;
 
-            if let Expr::Variable(name) = expr {
-                return Ok(Expr::AugAssign {
+            if let ExprKind::Variable { name, .. } = expr.kind {
+                let kind = ExprKind::AugAssign {
                     name,
                     operator,
                     value: Box::new(value),
-                });
+                    depth: None,
+                };
+                return Ok(self.node(kind, expr.span));
             }
 
-            self.token_error(&operator, "Invalid assignment target");
+            self.invalid_target(expr.span, &operator, "Invalid assignment target");
         }
 
         Ok(expr)
     }
 
+    /// Parses conditional expression. It groups to the right, so
+    /// `a ? b : c ? d : e` chains conditions like `else if` does.
+    fn conditional(&mut self) -> Result<Expr, ParseError> {
+        let test = self.or()?;
+
+        if self.match_type(TokenType::Question) {
+            let then: Expr = self.expression()?;
+            self.consume(TokenType::Colon, "Expected ':' after value of conditional")?;
+            let or_else: Expr = self.conditional()?;
+
+            let start: Span = test.span;
+            let kind = ExprKind::Conditional {
+                test: Box::new(test),
+                then: Box::new(then),
+                or_else: Box::new(or_else),
+            };
+            return Ok(self.node(kind, start));
+        }
+
+        Ok(test)
+    }
+
     /// Parses logical OR expression.
     fn or(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.and()?;
 
-        while self.match_type(TokenType::DoubleVBar) {
+        loop {
+            if self.match_type(TokenType::VBar) {
+                // Only match arms use a single `|`. Its right operand is still
+                // parsed to avoid follow-up errors.
+                self.report(ParseError::InvalidStatement {
+                    found: self.previous().clone(),
+                    message: String::from("Use '||' for logical or"),
+                });
+                self.and()?;
+                continue;
+            }
+            if !self.match_type(TokenType::DoubleVBar) {
+                break;
+            }
+
             let operator: Token = self.previous().clone();
             let right: Expr = self.and()?;
-            expr = Expr::Logical(Box::new(expr), operator, Box::new(right));
+            let start: Span = expr.span;
+            expr = self.node(
+                ExprKind::Logical(Box::new(expr), operator, Box::new(right)),
+                start,
+            );
         }
 
         Ok(expr)
@@ -238,7 +734,11 @@ impl Parser {
         while self.match_type(TokenType::DoubleAmper) {
             let operator: Token = self.previous().clone();
             let right: Expr = self.equality()?;
-            expr = Expr::Logical(Box::new(expr), operator, Box::new(right));
+            let start: Span = expr.span;
+            expr = self.node(
+                ExprKind::Logical(Box::new(expr), operator, Box::new(right)),
+                start,
+            );
         }
 
         Ok(expr)
@@ -251,7 +751,11 @@ impl Parser {
         while self.match_types(&[TokenType::BangEqual, TokenType::EqEqual]) {
             let operator: Token = self.previous().clone();
             let right: Expr = self.comparison()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            let start: Span = expr.span;
+            expr = self.node(
+                ExprKind::Binary(Box::new(expr), operator, Box::new(right)),
+                start,
+            );
         }
 
         Ok(expr)
@@ -269,7 +773,11 @@ impl Parser {
         ]) {
             let operator: Token = self.previous().clone();
             let right: Expr = self.term()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            let start: Span = expr.span;
+            expr = self.node(
+                ExprKind::Binary(Box::new(expr), operator, Box::new(right)),
+                start,
+            );
         }
 
         Ok(expr)
@@ -282,7 +790,11 @@ impl Parser {
         while self.match_types(&[TokenType::Minus, TokenType::Plus]) {
             let operator: Token = self.previous().clone();
             let right: Expr = self.factor()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            let start: Span = expr.span;
+            expr = self.node(
+                ExprKind::Binary(Box::new(expr), operator, Box::new(right)),
+                start,
+            );
         }
 
         Ok(expr)
@@ -295,7 +807,11 @@ impl Parser {
         while self.match_types(&[TokenType::Percent, TokenType::Slash, TokenType::Star]) {
             let operator: Token = self.previous().clone();
             let right: Expr = self.unary()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            let start: Span = expr.span;
+            expr = self.node(
+                ExprKind::Binary(Box::new(expr), operator, Box::new(right)),
+                start,
+            );
         }
 
         Ok(expr)
@@ -306,7 +822,8 @@ impl Parser {
         if self.match_types(&[TokenType::Bang, TokenType::Minus, TokenType::Plus]) {
             let operator: Token = self.previous().clone();
             let right: Expr = self.unary()?;
-            return Ok(Expr::Unary(operator, Box::new(right)));
+            let start: Span = operator.span;
+            return Ok(self.node(ExprKind::Unary(operator, Box::new(right)), start));
         }
 
         self.prefix()
@@ -318,23 +835,40 @@ impl Parser {
             let operator: Token = self.previous().clone();
             let expr = self.call()?;
 
-            if let Expr::Variable(name) = expr {
-                return Ok(Expr::Prefix { operator, name });
+            if let ExprKind::Variable { name, .. } = expr.kind {
+                let start: Span = operator.span;
+                let kind = ExprKind::Prefix {
+                    operator,
+                    name,
+                    depth: None,
+                };
+                return Ok(self.node(kind, start));
             }
 
-            self.token_error(&operator, "Invalid target in prefix operation");
+            self.invalid_target(expr.span, &operator, "Invalid target in prefix operation");
         }
 
         self.call()
     }
 
-    /// Parses function call expression.
+    /// Parses function call and subscript expression.
     fn call(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.suffix()?;
 
         loop {
             if self.match_type(TokenType::LParen) {
                 expr = self.finish_call(expr)?;
+            } else if self.match_type(TokenType::LBracket) {
+                let index: Expr = self.expression()?;
+                let bracket: Token =
+                    self.consume(TokenType::RBracket, "Expected ']' after index")?;
+                let start: Span = expr.span;
+                let kind = ExprKind::Index {
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
+                expr = self.node(kind, start);
             } else {
                 break;
             }
@@ -350,7 +884,10 @@ impl Parser {
         if !self.has_type(TokenType::RParen) {
             loop {
                 if arguments.len() >= 255 {
-                    self.token_error(self.peek(), "Can't have more than 255 arguments");
+                    self.report(ParseError::InvalidStatement {
+                        found: self.peek().clone(),
+                        message: String::from("Can't have more than 255 arguments"),
+                    });
                 }
 
                 arguments.push(self.expression()?);
@@ -363,7 +900,8 @@ impl Parser {
 
         let paren: Token = self.consume(TokenType::RParen, "Expected ')' after arguments")?;
 
-        Ok(Expr::Call(Box::new(callee), paren, arguments))
+        let start: Span = callee.span;
+        Ok(self.node(ExprKind::Call(Box::new(callee), paren, arguments), start))
     }
 
     // NOTE: This does not create a suffix AST node. It just desugars the suffix
@@ -376,11 +914,16 @@ impl Parser {
 
         if self.match_types(&[TokenType::DoubleMinus, TokenType::DoublePlus]) {
             let operator: Token = self.previous().clone();
-            if let Expr::Variable(name) = expr {
-                return Ok(Expr::Prefix { operator, name });
+            if let ExprKind::Variable { name, .. } = expr.kind {
+                let kind = ExprKind::Prefix {
+                    operator,
+                    name,
+                    depth: None,
+                };
+                return Ok(self.node(kind, expr.span));
             }
 
-            self.token_error(&operator, "Invalid target in suffix operation");
+            self.invalid_target(expr.span, &operator, "Invalid target in suffix operation");
         }
 
         Ok(expr)
@@ -388,19 +931,26 @@ impl Parser {
 
     /// Parses primary expression.
     fn primary(&mut self) -> Result<Expr, ParseError> {
+        let start: Span = self.peek().span;
+        let kind = self.primary_kind()?;
+        Ok(self.node(kind, start))
+    }
+
+    /// Parses the kind of primary expression that starts at the current token.
+    fn primary_kind(&mut self) -> Result<ExprKind, ParseError> {
         if self.match_type(TokenType::True) {
-            return Ok(Expr::Constant(Literal::True));
+            return Ok(ExprKind::Constant(Literal::True));
         }
         if self.match_type(TokenType::False) {
-            return Ok(Expr::Constant(Literal::False));
+            return Ok(ExprKind::Constant(Literal::False));
         }
         if self.match_type(TokenType::Null) {
-            return Ok(Expr::Constant(Literal::Null));
+            return Ok(ExprKind::Constant(Literal::Null));
         }
         if self.match_type(TokenType::Number) {
             match &self.previous().literal {
                 Some(Literal::Number(num)) => {
-                    return Ok(Expr::Constant(Literal::Number(*num)));
+                    return Ok(ExprKind::Constant(Literal::Number(*num)));
                 }
                 Some(_) => {}
                 None => {}
@@ -409,24 +959,175 @@ impl Parser {
         if self.match_type(TokenType::String) {
             match &self.previous().literal {
                 Some(Literal::String(str)) => {
-                    return Ok(Expr::Constant(Literal::String(str.into())));
+                    return Ok(ExprKind::Constant(Literal::String(str.into())));
                 }
                 Some(_) => {}
                 None => {}
             }
         }
+        if self.match_type(TokenType::FStringStart) {
+            return self.interpolation();
+        }
+        if self.match_type(TokenType::Func) {
+            return self.function_expression();
+        }
         if self.match_type(TokenType::LParen) {
+            if self.is_arrow_function() {
+                return self.arrow_function();
+            }
+
             let expr = self.expression()?;
             self.consume(TokenType::RParen, "Expected ')' after expression")?;
-            return Ok(Expr::Grouping(Box::new(expr)));
+            return Ok(ExprKind::Grouping(Box::new(expr)));
         }
         if self.match_type(TokenType::Ident) {
-            return Ok(Expr::Variable(self.previous().clone()));
+            return Ok(ExprKind::Variable {
+                name: self.previous().clone(),
+                depth: None,
+            });
+        }
+        if self.match_type(TokenType::LBracket) {
+            return self.list();
+        }
+        // Blocks are only parsed after statement headers, so a brace in
+        // expression position always starts a map literal.
+        if self.match_type(TokenType::LBrace) {
+            return self.map();
         }
 
         Err(ParseError::ExpectedExpression(self.peek().clone()))
     }
 
+    /// Parses anonymous function expression.
+    fn function_expression(&mut self) -> Result<ExprKind, ParseError> {
+        let keyword: Token = self.previous().clone();
+        self.consume(TokenType::LParen, "Expected '(' after 'func'")?;
+        let params: Vec<Param> = self.parameters()?;
+        let body: Vec<Stmt> = self.function_body()?;
+
+        Ok(ExprKind::Lambda {
+            keyword,
+            params: Rc::new(params),
+            body: Rc::new(body),
+        })
+    }
+
+    /// Parses arrow function expression. The body is a single expression whose
+    /// value is returned.
+    fn arrow_function(&mut self) -> Result<ExprKind, ParseError> {
+        let params: Vec<Param> = self.parameters()?;
+        let arrow: Token = self.consume(TokenType::Arrow, "Expected '=>' after parameters")?;
+
+        let loops = std::mem::take(&mut self.loops);
+        let value = self.expression();
+        self.loops = loops;
+
+        let value = value?;
+        let span: Span = value.span;
+        let body = Stmt::new(
+            StmtKind::Return {
+                keyword: arrow.clone(),
+                value: Some(value),
+            },
+            span,
+        );
+        Ok(ExprKind::Lambda {
+            keyword: arrow,
+            params: Rc::new(params),
+            body: Rc::new(Vec::from([body])),
+        })
+    }
+
+    /// Returns `true` if the tokens after an opening parenthesis are the
+    /// parameters of an arrow function, like `(a, b = 1) =>`. The parameters
+    /// end at the matching closing parenthesis, since default values may
+    /// contain parentheses of their own.
+    fn is_arrow_function(&self) -> bool {
+        let mut depth = 0;
+
+        for (i, token) in self.tokens.iter().enumerate().skip(self.current) {
+            match token.ty {
+                TokenType::LParen => depth += 1,
+                TokenType::RParen if depth > 0 => depth -= 1,
+                TokenType::RParen => {
+                    return self
+                        .tokens
+                        .get(i + 1)
+                        .is_some_and(|token| token.ty == TokenType::Arrow);
+                }
+                _ => {}
+            }
+        }
+
+        false
+    }
+
+    /// Parses interpolated string literal.
+    fn interpolation(&mut self) -> Result<ExprKind, ParseError> {
+        let mut parts: Vec<Expr> = Vec::new();
+
+        loop {
+            if self.match_type(TokenType::FStringMiddle) {
+                let token: &Token = self.previous();
+                if let Some(literal) = &token.literal {
+                    parts.push(Expr::new(ExprKind::Constant(literal.clone()), token.span));
+                }
+            } else if self.match_type(TokenType::LBrace) {
+                parts.push(self.expression()?);
+                self.consume(
+                    TokenType::RBrace,
+                    "Expected '}' after interpolated expression",
+                )?;
+            } else {
+                break;
+            }
+        }
+
+        self.consume(TokenType::FStringEnd, "Expected end of f-string")?;
+        Ok(ExprKind::Interpolation(parts))
+    }
+
+    /// Parses list literal.
+    fn list(&mut self) -> Result<ExprKind, ParseError> {
+        let mut elements: Vec<Expr> = Vec::new();
+
+        while !self.has_type(TokenType::RBracket) {
+            elements.push(self.expression()?);
+
+            if !self.match_type(TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::RBracket, "Expected ']' after list elements")?;
+        Ok(ExprKind::List(elements))
+    }
+
+    /// Parses map literal.
+    fn map(&mut self) -> Result<ExprKind, ParseError> {
+        let mut entries: Vec<(Expr, Expr)> = Vec::new();
+
+        while !self.has_type(TokenType::RBrace) {
+            let key = self.expression()?;
+            self.consume(TokenType::Colon, "Expected ':' after map key")?;
+            let value = self.expression()?;
+            entries.push((key, value));
+
+            if !self.match_type(TokenType::Comma) {
+                break;
+            }
+        }
+
+        let brace: Token = self.consume(TokenType::RBrace, "Expected '}' after map entries")?;
+        Ok(ExprKind::Map { brace, entries })
+    }
+
+    /// Creates an expression node that spans from the start position up to
+    /// and including the last consumed token.
+    fn node(&self, kind: ExprKind, start: Span) -> Expr {
+        Expr::new(kind, start.to(self.previous().span))
+    }
+
     /// Returns `true` if the current token has the given type. If so, it
     /// consumes the token.
     fn match_type(&mut self, ty: TokenType) -> bool {
@@ -463,6 +1164,20 @@ impl Parser {
         token_type::is_eof(self.peek().ty)
     }
 
+    /// Records an error that does not stop parsing the current statement.
+    fn report(&mut self, error: ParseError) {
+        self.errors.push(error);
+    }
+
+    /// Records an expression that cannot be the target of an operator.
+    fn invalid_target(&mut self, target: Span, operator: &Token, message: &'static str) {
+        self.report(ParseError::InvalidTarget {
+            target,
+            operator: operator.clone(),
+            message,
+        });
+    }
+
     /// Consumes the current token and returns it.
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
@@ -477,11 +1192,20 @@ impl Parser {
             return Ok(self.advance().clone());
         }
 
-        Err(ParseError::TokenMismatch {
-            expected: ty,
+        Err(self.mismatch(ty, message))
+    }
+
+    /// Creates the error for a token of the wrong type.
+    fn mismatch(&self, expected: TokenType, message: &str) -> ParseError {
+        ParseError::TokenMismatch {
+            expected,
             found: self.peek().clone(),
+            after: self
+                .current
+                .checked_sub(1)
+                .map_or(0, |i| self.tokens[i].span.end),
             message: message.to_string(),
-        })
+        }
     }
 
     /// Returns the current token which is yet to consume.
@@ -489,20 +1213,36 @@ impl Parser {
         &self.tokens[self.current]
     }
 
+    /// Returns the token after the current one.
+    fn peek_next(&self) -> &Token {
+        self.tokens
+            .get(self.current + 1)
+            .unwrap_or(&self.tokens[self.tokens.len() - 1])
+    }
+
     /// Returns the last consumed token.
     fn previous(&self) -> &Token {
         &self.tokens[self.current - 1]
     }
 }
 
-impl ErrorReporter for Parser {}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::internal::diagnostic::Source;
+
+    fn token(ty: TokenType, lexeme: &str, line: usize, column: usize, start: usize) -> Token {
+        let span = Span::new(start, start + lexeme.len());
+        Token::new(ty, String::from(lexeme), None, line, column, span)
+    }
+
+    fn variable(name: Token) -> Expr {
+        let span = name.span;
+        Expr::new(ExprKind::Variable { name, depth: None }, span)
+    }
 
     #[test]
-    fn test_parse() -> Result<(), ParseError> {
+    fn test_parse() -> Result<(), Vec<ParseError>> {
         let input = "\
             a = 5;
             b = 10;
@@ -516,120 +1256,277 @@ mod tests {
         ";
 
         let statements = Vec::from([
-            Stmt::Expr(Expr::Assign(
-                Token {
-                    ty: TokenType::Ident,
-                    lexeme: String::from("a"),
-                    literal: None,
-                    line: 1,
-                },
-                Box::new(Expr::Constant(Literal::Number(5.0))),
-            )),
-            Stmt::Expr(Expr::Assign(
-                Token {
-                    ty: TokenType::Ident,
-                    lexeme: String::from("b"),
-                    literal: None,
-                    line: 2,
-                },
-                Box::new(Expr::Constant(Literal::Number(10.0))),
-            )),
-            Stmt::Function {
-                name: Token {
-                    ty: TokenType::Ident,
-                    lexeme: String::from("add"),
-                    literal: None,
-                    line: 4,
-                },
-                params: Vec::from([
-                    Token {
-                        ty: TokenType::Ident,
-                        lexeme: String::from("a"),
-                        literal: None,
-                        line: 4,
-                    },
-                    Token {
-                        ty: TokenType::Ident,
-                        lexeme: String::from("b"),
-                        literal: None,
-                        line: 4,
+            Stmt::new(
+                StmtKind::Expr(Expr::new(
+                    ExprKind::Assign {
+                        name: token(TokenType::Ident, "a", 1, 1, 0),
+                        value: Box::new(Expr::new(
+                            ExprKind::Constant(Literal::Number(5.0)),
+                            Span::new(4, 5),
+                        )),
+                        depth: None,
                     },
-                ]),
-                body: Vec::from([Stmt::Return {
-                    keyword: Token {
-                        ty: TokenType::Return,
-                        lexeme: String::from("return"),
-                        literal: None,
-                        line: 5,
+                    Span::new(0, 5),
+                )),
+                Span::new(0, 6),
+            ),
+            Stmt::new(
+                StmtKind::Expr(Expr::new(
+                    ExprKind::Assign {
+                        name: token(TokenType::Ident, "b", 2, 13, 19),
+                        value: Box::new(Expr::new(
+                            ExprKind::Constant(Literal::Number(10.0)),
+                            Span::new(23, 25),
+                        )),
+                        depth: None,
                     },
-                    value: Some(Expr::Binary(
-                        Box::new(Expr::Variable(Token {
-                            ty: TokenType::Ident,
-                            lexeme: String::from("a"),
-                            literal: None,
-                            line: 5,
-                        })),
-                        Token {
-                            ty: TokenType::Plus,
-                            lexeme: String::from("+"),
-                            literal: None,
-                            line: 5,
+                    Span::new(19, 25),
+                )),
+                Span::new(19, 26),
+            ),
+            Stmt::new(
+                StmtKind::Function {
+                    name: token(TokenType::Ident, "add", 4, 18, 45),
+                    params: Rc::new(Vec::from([
+                        Param {
+                            name: token(TokenType::Ident, "a", 4, 22, 49),
+                            kind: ParamKind::Required,
                         },
-                        Box::new(Expr::Variable(Token {
-                            ty: TokenType::Ident,
-                            lexeme: String::from("b"),
-                            literal: None,
-                            line: 5,
-                        })),
-                    )),
-                }]),
-            },
-            Stmt::Expr(Expr::Assign(
-                Token {
-                    ty: TokenType::Ident,
-                    lexeme: String::from("result"),
-                    literal: None,
-                    line: 8,
+                        Param {
+                            name: token(TokenType::Ident, "b", 4, 25, 52),
+                            kind: ParamKind::Required,
+                        },
+                    ])),
+                    body: Rc::new(Vec::from([Stmt::new(
+                        StmtKind::Return {
+                            keyword: token(TokenType::Return, "return", 5, 17, 73),
+                            value: Some(Expr::new(
+                                ExprKind::Binary(
+                                    Box::new(variable(token(TokenType::Ident, "a", 5, 24, 80))),
+                                    token(TokenType::Plus, "+", 5, 26, 82),
+                                    Box::new(variable(token(TokenType::Ident, "b", 5, 28, 84))),
+                                ),
+                                Span::new(80, 85),
+                            )),
+                        },
+                        Span::new(73, 86),
+                    )])),
                 },
-                Box::new(Expr::Call(
-                    Box::new(Expr::Variable(Token {
-                        ty: TokenType::Ident,
-                        lexeme: String::from("add"),
-                        literal: None,
-                        line: 8,
-                    })),
-                    Token {
-                        ty: TokenType::RParen,
-                        lexeme: String::from(")"),
-                        literal: None,
-                        line: 8,
+                Span::new(40, 100),
+            ),
+            Stmt::new(
+                StmtKind::Expr(Expr::new(
+                    ExprKind::Assign {
+                        name: token(TokenType::Ident, "result", 8, 13, 114),
+                        value: Box::new(Expr::new(
+                            ExprKind::Call(
+                                Box::new(variable(token(TokenType::Ident, "add", 8, 22, 123))),
+                                token(TokenType::RParen, ")", 8, 30, 131),
+                                Vec::from([
+                                    variable(token(TokenType::Ident, "a", 8, 26, 127)),
+                                    variable(token(TokenType::Ident, "b", 8, 29, 130)),
+                                ]),
+                            ),
+                            Span::new(123, 132),
+                        )),
+                        depth: None,
                     },
-                    Vec::from([
-                        Expr::Variable(Token {
-                            ty: TokenType::Ident,
-                            lexeme: String::from("a"),
-                            literal: None,
-                            line: 8,
-                        }),
-                        Expr::Variable(Token {
-                            ty: TokenType::Ident,
-                            lexeme: String::from("b"),
-                            literal: None,
-                            line: 8,
-                        }),
-                    ]),
+                    Span::new(114, 132),
                 )),
-            )),
-            Stmt::Echo(Expr::Variable(Token {
-                ty: TokenType::Ident,
-                lexeme: String::from("result"),
-                literal: None,
-                line: 9,
-            })),
+                Span::new(114, 133),
+            ),
+            Stmt::new(
+                StmtKind::Echo(variable(token(TokenType::Ident, "result", 9, 18, 151))),
+                Span::new(146, 158),
+            ),
         ]);
 
         let mut parser = Parser::new(input);
         assert_eq!(parser.parse()?, statements);
         Ok(())
     }
+
+    #[test]
+    fn test_interpolation() -> Result<(), Vec<ParseError>> {
+        let statements = Vec::from([Stmt::new(
+            StmtKind::Echo(Expr::new(
+                ExprKind::Interpolation(Vec::from([
+                    Expr::new(
+                        ExprKind::Constant(Literal::String(String::from("total: "))),
+                        Span::new(7, 14),
+                    ),
+                    variable(token(TokenType::Ident, "total", 1, 16, 15)),
+                    Expr::new(
+                        ExprKind::Constant(Literal::String(String::from("{}"))),
+                        Span::new(21, 25),
+                    ),
+                ])),
+                Span::new(5, 26),
+            )),
+            Span::new(0, 27),
+        )]);
+
+        let mut parser = Parser::new("echo f\"total: {total}{{}}\";");
+        assert_eq!(parser.parse()?, statements);
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_control() {
+        let input = "outer: while true { for x in xs { continue outer; } break; }";
+        assert!(Parser::new(input).parse().is_ok());
+
+        assert!(Parser::new("break;").parse().is_err());
+        assert!(Parser::new("while true { func f() { continue; } }")
+            .parse()
+            .is_err());
+        assert!(Parser::new("while true { break outer; }").parse().is_err());
+    }
+
+    #[test]
+    fn test_conditional() {
+        let statements = Parser::new("x = a || b ? 1 : c ? 2 : 3;").parse().unwrap();
+        let StmtKind::Expr(Expr {
+            kind: ExprKind::Assign { value, .. },
+            ..
+        }) = &statements[0].kind
+        else {
+            panic!("Expected assignment");
+        };
+        let ExprKind::Conditional { test, or_else, .. } = &value.kind else {
+            panic!("Expected conditional, found {:?}", value.kind);
+        };
+        assert!(matches!(test.kind, ExprKind::Logical(..)));
+        assert!(matches!(or_else.kind, ExprKind::Conditional { .. }));
+
+        assert!(Parser::new("a ? b : c = 1;").parse().is_err());
+        assert!(Parser::new("a ? b;").parse().is_err());
+    }
+
+    #[test]
+    fn test_parameters() {
+        let statements = Parser::new("f = (a, b = g(1), ...rest) => a;")
+            .parse()
+            .unwrap();
+        let StmtKind::Expr(Expr {
+            kind: ExprKind::Assign { value, .. },
+            ..
+        }) = &statements[0].kind
+        else {
+            panic!("Expected assignment");
+        };
+        let ExprKind::Lambda { params, .. } = &value.kind else {
+            panic!("Expected arrow function, found {:?}", value.kind);
+        };
+        let names: Vec<&str> = params.iter().map(|p| p.name.lexeme.as_str()).collect();
+        assert_eq!(names, ["a", "b", "rest"]);
+        assert!(matches!(params[1].kind, ParamKind::Optional(_)));
+        assert_eq!(params[2].kind, ParamKind::Rest);
+
+        assert!(Parser::new("func f(a = 1, b) {}").parse().is_err());
+        assert!(Parser::new("func f(...a, b) {}").parse().is_err());
+    }
+
+    #[test]
+    fn test_match() {
+        let input = "match x { 1 | -2 => echo 1; \"a\"..\"f\" => { echo 2; }, _ => {} }";
+        let mut parser = Parser::new(input);
+        let statements = parser.parse().unwrap();
+        assert!(parser.warnings().is_empty());
+
+        let StmtKind::Match { arms, .. } = &statements[0].kind else {
+            panic!("Expected match statement");
+        };
+        let kinds: Vec<Vec<PatternKind>> = arms
+            .iter()
+            .map(|arm| arm.patterns.iter().map(|p| p.kind.clone()).collect())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                vec![
+                    PatternKind::Literal(Literal::Number(1.0)),
+                    PatternKind::Literal(Literal::Number(-2.0)),
+                ],
+                vec![PatternKind::Range {
+                    start: Literal::String(String::from("a")),
+                    end: Literal::String(String::from("f")),
+                }],
+                vec![PatternKind::Wildcard],
+            ]
+        );
+
+        let mut parser = Parser::new("match x { 1 => {} }");
+        assert!(parser.parse().is_ok());
+        assert_eq!(parser.warnings().len(), 1);
+
+        assert!(Parser::new("match x { 1..true => {} _ => {} }")
+            .parse()
+            .is_err());
+
+        // A single `|` outside a match arm is one error, not a lexical one
+        let errors = Parser::new("echo a | b;").parse().unwrap_err();
+        assert!(matches!(errors[..], [ParseError::InvalidStatement { .. }]));
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let input = "\
+            a = ;
+            if a { b = 1 + ; c = 2; }
+            func f() { return 1 }
+            1 = 2;
+            echo a;
+        ";
+
+        let (statements, errors) = Parser::new(input).parse_partial();
+        let lines: Vec<usize> = errors.iter().map(|error| error.line()).collect();
+        assert_eq!(lines, [1, 2, 3, 4]);
+
+        // Statements around the errors are kept, including the rest of the
+        // block after a failed statement
+        assert_eq!(statements.len(), 4);
+        match &statements[0].kind {
+            StmtKind::If { body, .. } => assert_eq!(body.len(), 1),
+            kind => panic!("Expected if statement, found {kind:?}"),
+        }
+        assert!(matches!(statements[3].kind, StmtKind::Echo(_)));
+
+        // Lexical errors fail parsing even if the remaining tokens are valid
+        let errors = Parser::new("echo 1 ~;").parse().unwrap_err();
+        assert!(matches!(errors[..], [ParseError::Lex(_)]));
+
+        // A missing ';' is reported right after the statement
+        let source = Source {
+            name: "main.ck",
+            text: "a = 1\nb = 2;",
+        };
+        let errors = Parser::new(source.text).parse().unwrap_err();
+        assert!(errors[0]
+            .diagnostic()
+            .render(&source, false)
+            .contains(" --> main.ck:1:6\n"));
+    }
+
+    #[test]
+    fn test_starting_at() {
+        let input = "func f() { return x; }\necho f();\nassert f();\n";
+        let start = input.find("echo").unwrap();
+
+        let statements = Parser::starting_at(&input[start..], start, 2)
+            .parse()
+            .unwrap();
+        assert_eq!(statements.len(), 2);
+        assert_eq!(
+            &input[statements[0].span.start..statements[0].span.end],
+            "echo f();"
+        );
+        match &statements[1].kind {
+            StmtKind::Assert { source, .. } => assert_eq!(source, "f()"),
+            kind => panic!("Expected assert statement, found {kind:?}"),
+        }
+
+        let errors = Parser::starting_at("b = ;", 7, 2).parse().unwrap_err();
+        assert_eq!(errors[0].line(), 2);
+    }
 }
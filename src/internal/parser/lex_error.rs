@@ -0,0 +1,79 @@
+use std::fmt;
+
+use super::parse_error::SYNTAX_ERROR;
+use crate::internal::diagnostic::Diagnostic;
+use crate::internal::token::Span;
+
+/// An error found while scanning source code.
+#[derive(Clone)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
+}
+
+/// All possible error types in the lexer.
+#[derive(Clone, PartialEq, Debug)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    /// A single `&`
+    SingleAmpersand,
+    UnterminatedString,
+    /// A single `}` in the text of an f-string
+    SingleBrace,
+    UnterminatedInterpolation,
+    InvalidEscape(char),
+    /// A `\u` escape without its braces
+    MalformedUnicodeEscape,
+    InvalidUnicodeEscape(String),
+}
+
+impl fmt::Debug for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}:{}] LexError: {}",
+            self.line,
+            self.column,
+            self.message()
+        )
+    }
+}
+
+impl LexError {
+    /// Returns the error message without its location.
+    pub fn message(&self) -> String {
+        match &self.kind {
+            LexErrorKind::UnexpectedCharacter(c) => format!("Unexpected character '{c}'"),
+            LexErrorKind::SingleAmpersand => String::from("Expected another '&'"),
+            LexErrorKind::UnterminatedString => String::from("Unterminated string"),
+            LexErrorKind::SingleBrace => String::from("Single '}' is not allowed in f-string"),
+            LexErrorKind::UnterminatedInterpolation => {
+                String::from("Expected '}' after interpolated expression")
+            }
+            LexErrorKind::InvalidEscape(c) => format!("Invalid escape sequence '\\{c}'"),
+            LexErrorKind::MalformedUnicodeEscape => {
+                String::from("Expected '\\u{...}' for unicode escape")
+            }
+            LexErrorKind::InvalidUnicodeEscape(digits) => {
+                format!("Invalid unicode escape '\\u{{{digits}}}'")
+            }
+        }
+    }
+
+    /// Creates a diagnostic pointing at the offending characters.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::new(SYNTAX_ERROR, &self.message(), self.span);
+
+        match self.kind {
+            LexErrorKind::SingleAmpersand => diagnostic.with_help("use '&&' for logical and"),
+            LexErrorKind::UnterminatedString => diagnostic.with_label("string starts here"),
+            LexErrorKind::SingleBrace => diagnostic.with_help("use '}}' for a literal brace"),
+            LexErrorKind::InvalidEscape(_) => diagnostic
+                .with_note("valid escapes are \\n, \\t, \\r, \\0, \\\\, \\\", \\' and \\u{...}")
+                .with_help("use a raw string to keep backslashes"),
+            _ => diagnostic,
+        }
+    }
+}
@@ -1,26 +1,48 @@
 use std::collections::HashMap;
 
-use super::error_reporter::ErrorReporter;
-use crate::internal::token::{Literal, Token, TokenType};
+use super::lex_error::{LexError, LexErrorKind};
+use crate::internal::token::{Literal, Span, Token, TokenType};
 
 /// A lexer for Chonk source code.
 pub struct Lexer {
     input: String,
+    // Byte offset of the input in the whole source, which spans are relative to
+    offset: usize,
     tokens: Vec<Token>,
+    // Byte offsets of the first character of the current lexeme and of the
+    // next character to consume
     start: usize,
     current: usize,
+    // Position of the next character to consume
     line: usize,
+    column: usize,
+    // Position of the first character of the current lexeme
+    start_line: usize,
+    start_column: usize,
     keywords: HashMap<String, TokenType>,
+    errors: Vec<LexError>,
+}
+
+/// A location in the source code.
+#[derive(Clone, Copy)]
+struct Position {
+    offset: usize,
+    line: usize,
+    column: usize,
 }
 
 impl Default for Lexer {
     fn default() -> Self {
         Self {
             input: String::new(),
+            offset: 0,
             tokens: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
             keywords: HashMap::from([
                 ("null", TokenType::Null),
                 ("true", TokenType::True),
@@ -29,13 +51,24 @@ impl Default for Lexer {
                 ("if", TokenType::If),
                 ("else", TokenType::Else),
                 ("while", TokenType::While),
+                ("for", TokenType::For),
+                ("in", TokenType::In),
+                ("break", TokenType::Break),
+                ("continue", TokenType::Continue),
                 ("return", TokenType::Return),
                 ("del", TokenType::Delete),
                 ("echo", TokenType::Echo),
+                ("assert", TokenType::Assert),
+                ("try", TokenType::Try),
+                ("catch", TokenType::Catch),
+                ("finally", TokenType::Finally),
+                ("throw", TokenType::Throw),
+                ("match", TokenType::Match),
             ])
             .into_iter()
             .map(|(key, value)| (String::from(key), value))
             .collect(),
+            errors: Vec::new(),
         }
     }
 }
@@ -49,19 +82,43 @@ impl Lexer {
         }
     }
 
+    /// Creates a new `Lexer` for a part of a larger source, which starts a
+    /// line at the given byte offset and line number. Tokens keep their
+    /// position in the whole source.
+    pub fn starting_at(input: &str, offset: usize, line: usize) -> Self {
+        Self {
+            offset,
+            line,
+            start_line: line,
+            ..Self::new(input)
+        }
+    }
+
     /// Adds tokens from source until character ends.
     pub fn scan_tokens(&mut self) -> &[Token] {
         while !self.is_at_end() {
             // We are at the beginning of the next lexeme
-            self.start = self.current;
+            self.begin_lexeme();
             self.scan_token();
         }
 
-        self.tokens
-            .push(Token::new(TokenType::Eof, String::new(), None, self.line));
+        let span = self.span(self.current, self.current);
+        self.tokens.push(Token::new(
+            TokenType::Eof,
+            String::new(),
+            None,
+            self.line,
+            self.column,
+            span,
+        ));
         &self.tokens
     }
 
+    /// Returns the errors found while scanning.
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
     /// Adds token type for the next character.
     fn scan_token(&mut self) {
         use TokenType::*;
@@ -72,8 +129,12 @@ impl Lexer {
             ')' => self.add_token(RParen),
             '{' => self.add_token(LBrace),
             '}' => self.add_token(RBrace),
+            '[' => self.add_token(LBracket),
+            ']' => self.add_token(RBracket),
             ',' => self.add_token(Comma),
+            ':' => self.add_token(Colon),
             ';' => self.add_token(Semicolon),
+            '?' => self.add_token(Question),
 
             '+' => {
                 if self.match_char('+') {
@@ -118,6 +179,8 @@ impl Lexer {
             '=' => {
                 if self.match_char('=') {
                     self.add_token(EqEqual);
+                } else if self.match_char('>') {
+                    self.add_token(Arrow);
                 } else {
                     self.add_token(Equal);
                 }
@@ -149,14 +212,26 @@ impl Lexer {
                 if self.match_char('&') {
                     self.add_token(DoubleAmper);
                 } else {
-                    self.error(self.line, "Expected another '&'");
+                    // Scan it as intended to avoid follow-up errors
+                    self.error(LexErrorKind::SingleAmpersand, self.lexeme_start());
+                    self.add_token(DoubleAmper);
                 }
             }
             '|' => {
+                // A single `|` separates the patterns of a match arm, so the
+                // parser reports it elsewhere
                 if self.match_char('|') {
                     self.add_token(DoubleVBar);
                 } else {
-                    self.error(self.line, "Expected another '|'");
+                    self.add_token(VBar);
+                }
+            }
+            '.' if self.peek() == '.' => {
+                self.advance();
+                if self.match_char('.') {
+                    self.add_token(Ellipsis);
+                } else {
+                    self.add_token(DotDot);
                 }
             }
 
@@ -167,24 +242,64 @@ impl Lexer {
                 }
             }
 
-            ' ' | '\t' => {}
-            '\n' | '\r' => self.line += 1,
+            ' ' | '\t' | '\n' | '\r' => {}
 
-            '\'' => self.add_string('\''),
-            '"' => self.add_string('"'),
+            '\'' => self.add_string('\'', false),
+            '"' => self.add_string('"', false),
 
             _ => {
-                if c.is_ascii_digit() {
+                if c == 'r' && (self.peek() == '"' || self.peek() == '\'') {
+                    let delimiter = self.advance();
+                    self.add_string(delimiter, true);
+                } else if c == 'f' && (self.peek() == '"' || self.peek() == '\'') {
+                    let delimiter = self.advance();
+                    self.add_fstring(delimiter);
+                } else if c.is_ascii_digit() {
                     self.add_number();
                 } else if is_potential_identifier_start(c) {
                     self.add_identifier();
                 } else {
-                    self.error(self.line, &format!("Unexpected character '{c}'"));
+                    self.error(LexErrorKind::UnexpectedCharacter(c), self.lexeme_start());
                 }
             }
         }
     }
 
+    /// Returns the position of the next character to consume.
+    fn position(&self) -> Position {
+        Position {
+            offset: self.current,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Returns the position of the first character of the current lexeme.
+    fn lexeme_start(&self) -> Position {
+        Position {
+            offset: self.start,
+            line: self.start_line,
+            column: self.start_column,
+        }
+    }
+
+    /// Records an error from the given position up to the next character.
+    fn error(&mut self, kind: LexErrorKind, from: Position) {
+        self.errors.push(LexError {
+            kind,
+            line: from.line,
+            column: from.column,
+            span: self.span(from.offset, self.current),
+        });
+    }
+
+    /// Marks the next character as the start of a new lexeme.
+    fn begin_lexeme(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
+    }
+
     /// Creates a new token.
     fn add_token(&mut self, ty: TokenType) {
         self.add_token_literal(ty, None);
@@ -193,28 +308,206 @@ impl Lexer {
     /// Creates a new token with literal.
     fn add_token_literal(&mut self, ty: TokenType, literal: Option<Literal>) {
         let text = self.input[self.start..self.current].to_string();
-        self.tokens.push(Token::new(ty, text, literal, self.line));
+        self.tokens.push(Token::new(
+            ty,
+            text,
+            literal,
+            self.start_line,
+            self.start_column,
+            self.span(self.start, self.current),
+        ));
+    }
+
+    /// Returns the span between two byte offsets of the input.
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span::new(self.offset + start, self.offset + end)
+    }
+
+    /// Adds string literal token. Escape sequences are processed unless the
+    /// string is raw. Triple-quoted strings may span multiple lines and have
+    /// their common indentation removed.
+    fn add_string(&mut self, delimiter: char, raw: bool) {
+        let triple = self.peek() == delimiter && self.peek_next() == delimiter;
+        let closing = delimiter.to_string().repeat(if triple { 3 } else { 1 });
+        if triple {
+            self.advance();
+            self.advance();
+        }
+
+        let mut value = String::new();
+        while !self.check_str(&closing) && !self.is_at_end() {
+            self.string_char(&mut value, raw);
+        }
+
+        if self.is_at_end() {
+            self.error(LexErrorKind::UnterminatedString, self.lexeme_start());
+            return;
+        }
+
+        // The closing quotes
+        for _ in closing.chars() {
+            self.advance();
+        }
+
+        if triple {
+            value = dedent(&value);
+        }
+        self.add_token_literal(TokenType::String, Some(Literal::String(value)));
     }
 
-    /// Adds string literal token.
-    fn add_string(&mut self, delimiter: char) {
+    /// Consumes a character inside a string literal and appends it to the
+    /// string value.
+    fn string_char(&mut self, value: &mut String, raw: bool) {
+        let escape = self.position();
+        let c = self.advance();
+        match c {
+            '\\' if !raw => {
+                if let Some(escaped) = self.escape_sequence(escape) {
+                    value.push(escaped);
+                }
+            }
+            _ => value.push(c),
+        }
+    }
+
+    /// Adds the tokens of an interpolated string. Literal text becomes
+    /// `FStringMiddle` tokens, and every interpolated expression is scanned as
+    /// regular tokens between a pair of braces.
+    fn add_fstring(&mut self, delimiter: char) {
+        let opening = self.lexeme_start();
+        self.add_token(TokenType::FStringStart);
+        self.begin_lexeme();
+        let mut value = String::new();
+
         while self.peek() != delimiter && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            match self.peek() {
+                // Doubled braces are literal braces
+                c @ ('{' | '}') if self.peek_next() == c => {
+                    self.advance();
+                    self.advance();
+                    value.push(c);
+                }
+                '{' => {
+                    self.add_fstring_middle(&mut value);
+                    self.begin_lexeme();
+                    self.advance();
+                    self.add_token(TokenType::LBrace);
+                    self.scan_interpolation();
+                    self.begin_lexeme();
+                }
+                '}' => {
+                    let brace = self.position();
+                    self.advance();
+                    self.error(LexErrorKind::SingleBrace, brace);
+                }
+                _ => self.string_char(&mut value, false),
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            self.error(self.line, "Unterminated string");
+            self.error(LexErrorKind::UnterminatedString, opening);
+            return;
         }
 
+        self.add_fstring_middle(&mut value);
+
         // The closing quote
+        self.begin_lexeme();
         self.advance();
+        self.add_token(TokenType::FStringEnd);
+    }
 
-        // Trim the surrounding quotes
-        let value = self.input[(self.start + 1)..(self.current - 1)].to_string();
-        self.add_token_literal(TokenType::String, Some(Literal::String(value)));
+    /// Adds the literal text of an interpolated string scanned so far.
+    fn add_fstring_middle(&mut self, value: &mut String) {
+        if !value.is_empty() {
+            let literal = Literal::String(std::mem::take(value));
+            self.add_token_literal(TokenType::FStringMiddle, Some(literal));
+        }
+    }
+
+    /// Scans the tokens of an interpolated expression up to and including its
+    /// closing brace.
+    fn scan_interpolation(&mut self) {
+        let mut depth = 0;
+
+        loop {
+            if self.is_at_end() {
+                self.error(LexErrorKind::UnterminatedInterpolation, self.position());
+                return;
+            }
+            if self.peek() == '}' && depth == 0 {
+                self.begin_lexeme();
+                self.advance();
+                self.add_token(TokenType::RBrace);
+                return;
+            }
+
+            self.begin_lexeme();
+            let count = self.tokens.len();
+            self.scan_token();
+
+            // Track braces of map literals inside the expression
+            for token in &self.tokens[count..] {
+                match token.ty {
+                    TokenType::LBrace => depth += 1,
+                    TokenType::RBrace => depth -= 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Returns the character of the escape sequence following the backslash
+    /// at the given position.
+    fn escape_sequence(&mut self, escape: Position) -> Option<char> {
+        if self.is_at_end() {
+            return None;
+        }
+
+        match self.advance() {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            '\'' => Some('\''),
+            'u' => self.unicode_escape(escape),
+            c => {
+                self.error(LexErrorKind::InvalidEscape(c), escape);
+                None
+            }
+        }
+    }
+
+    /// Returns the character of a `\u{XXXX}` escape sequence.
+    fn unicode_escape(&mut self, escape: Position) -> Option<char> {
+        if !self.match_char('{') {
+            self.error(LexErrorKind::MalformedUnicodeEscape, escape);
+            return None;
+        }
+
+        let mut digits = String::new();
+        while self.peek().is_ascii_hexdigit() {
+            digits.push(self.advance());
+        }
+
+        if !self.match_char('}') {
+            self.error(LexErrorKind::MalformedUnicodeEscape, escape);
+            return None;
+        }
+
+        let code = match digits.len() {
+            1..=6 => u32::from_str_radix(&digits, 16).ok(),
+            _ => None,
+        };
+        match code.and_then(char::from_u32) {
+            Some(c) => Some(c),
+            None => {
+                self.error(LexErrorKind::InvalidUnicodeEscape(digits), escape);
+                None
+            }
+        }
     }
 
     /// Adds number literal token.
@@ -256,44 +549,79 @@ impl Lexer {
         self.current >= self.input.len()
     }
 
+    /// Returns the source code that has not been consumed yet.
+    fn rest(&self) -> &str {
+        &self.input[self.current..]
+    }
+
     /// Consumes the current character if it's what we're looking for.
     fn match_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-
-        if self.input.chars().nth(self.current).unwrap() != expected {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
 
-        self.current += 1;
+        self.advance();
         true
     }
 
+    /// Returns `true` if the source code continues with the expected text.
+    fn check_str(&self, expected: &str) -> bool {
+        self.rest().starts_with(expected)
+    }
+
     /// Consumes and returns the next character in the source code.
     fn advance(&mut self) -> char {
-        if !self.is_at_end() {
-            self.current += 1;
+        let c = self.peek();
+        if self.is_at_end() {
+            return c;
+        }
+        self.current += c.len_utf8();
+
+        // A carriage return only ends a line when it is not part of "\r\n"
+        if c == '\n' || (c == '\r' && self.peek() != '\n') {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
         }
-        self.input.chars().nth(self.current - 1).unwrap()
+        c
     }
 
     /// Similar to `advance()`, but doesn't consume the character. This is called
     /// "lookahead".
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        self.input.chars().nth(self.current).unwrap()
+        self.rest().chars().next().unwrap_or('\0')
     }
 
     /// Similar to `peek()`, but checks out the next character instead.
     fn peek_next(&self) -> char {
-        if (self.current + 1) >= self.input.len() {
-            return '\0';
-        }
-        self.input.chars().nth(self.current + 1).unwrap()
+        self.rest().chars().nth(1).unwrap_or('\0')
+    }
+}
+
+/// Removes the first and last lines of a triple-quoted string if they are
+/// blank, and strips the indentation common to all non-blank lines.
+fn dedent(text: &str) -> String {
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    if lines.len() > 1 && lines[0].trim().is_empty() {
+        lines.remove(0);
     }
+    if lines.len() > 1 && lines[lines.len() - 1].trim().is_empty() {
+        lines.pop();
+    }
+
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or(""))
+        .collect::<Vec<&str>>()
+        .join("\n")
 }
 
 /// Returns `true` if character is a potential start for an identifier.
@@ -306,8 +634,6 @@ fn is_potential_identifier_char(c: char) -> bool {
     c.is_alphanumeric() || c == '_'
 }
 
-impl ErrorReporter for Lexer {}
-
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
@@ -406,4 +732,128 @@ mod tests {
 
         assert_eq!(scanned_tokens, test_tokens);
     }
+
+    #[test]
+    fn test_string_escapes() {
+        let input = r#""a\tb\n\"c\" \u{1F600}" r"C:\new\d+""#;
+        let mut lexer = Lexer::new(input);
+
+        let literals: Vec<Option<Literal>> = lexer
+            .scan_tokens()
+            .iter()
+            .map(|token| token.literal.clone())
+            .collect();
+        assert_eq!(
+            literals,
+            [
+                Some(Literal::String(String::from("a\tb\n\"c\" \u{1F600}"))),
+                Some(Literal::String(String::from(r"C:\new\d+"))),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_triple_quoted_string() {
+        let input = "s = \"\"\"\n    SELECT *\n      FROM t\n    \"\"\";\nend";
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.scan_tokens();
+
+        assert_eq!(
+            tokens[2].literal,
+            Some(Literal::String(String::from("SELECT *\n  FROM t")))
+        );
+        assert_eq!(tokens[4].lexeme, "end");
+        assert_eq!(tokens[4].line, 5);
+    }
+
+    #[test]
+    fn test_unicode() {
+        let input = "café = \"héllo 😀\";\nΔ = f\"{café}ü\";";
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.scan_tokens();
+
+        let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+        assert_eq!(
+            lexemes,
+            [
+                "café",
+                "=",
+                "\"héllo 😀\"",
+                ";",
+                "Δ",
+                "=",
+                "f\"",
+                "{",
+                "café",
+                "}",
+                "ü",
+                "\"",
+                ";",
+                "",
+            ]
+        );
+        assert_eq!(
+            tokens[2].literal,
+            Some(Literal::String(String::from("héllo 😀")))
+        );
+        assert_eq!(tokens[4].line, 2);
+    }
+
+    #[test]
+    fn test_positions() {
+        let input = "a = 1;\r\nécho = \"x\r\ny\";\rb;";
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.scan_tokens();
+
+        let positions: Vec<(&str, usize, usize)> = tokens
+            .iter()
+            .map(|token| (token.lexeme.as_str(), token.line, token.column))
+            .collect();
+        assert_eq!(
+            positions,
+            [
+                ("a", 1, 1),
+                ("=", 1, 3),
+                ("1", 1, 5),
+                (";", 1, 6),
+                ("écho", 2, 1),
+                ("=", 2, 6),
+                ("\"x\r\ny\"", 2, 8),
+                (";", 3, 3),
+                ("b", 4, 1),
+                (";", 4, 2),
+                ("", 4, 3),
+            ]
+        );
+        assert_eq!(tokens[4].span, Span::new(8, 13));
+        assert_eq!(
+            &input[tokens[6].span.start..tokens[6].span.end],
+            "\"x\r\ny\""
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let input = "a = 1 & 2;\nb = \"\\q\" ~ f\"}\";\nc = \"open";
+        let mut lexer = Lexer::new(input);
+        lexer.scan_tokens();
+
+        let errors: Vec<(LexErrorKind, usize, usize)> = lexer
+            .errors()
+            .iter()
+            .map(|error| (error.kind.clone(), error.line, error.column))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (LexErrorKind::SingleAmpersand, 1, 7),
+                (LexErrorKind::InvalidEscape('q'), 2, 6),
+                (LexErrorKind::UnexpectedCharacter('~'), 2, 10),
+                (LexErrorKind::SingleBrace, 2, 14),
+                (LexErrorKind::UnterminatedString, 3, 5),
+            ]
+        );
+        assert_eq!(lexer.errors()[1].span, Span::new(16, 18));
+    }
 }
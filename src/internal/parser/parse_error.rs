@@ -1,38 +1,133 @@
 use std::fmt;
 
-use crate::internal::token::{Token, TokenType};
+use super::lex_error::LexError;
+use crate::internal::diagnostic::Diagnostic;
+use crate::internal::token::{Span, Token, TokenType};
+
+/// Kind of the errors found while scanning and parsing.
+pub const SYNTAX_ERROR: &str = "SyntaxError";
 
 /// All possible error types in the parser.
 pub enum ParseError {
+    Lex(LexError),
     ExpectedExpression(Token),
+    ExpectedPattern(Token),
     TokenMismatch {
         expected: TokenType,
+        found: Token,
+        // End of the previous token, where a missing ';' is reported since the
+        // next token may be on a later line
+        after: usize,
+        message: String,
+    },
+    InvalidStatement {
         found: Token,
         message: String,
     },
+    InvalidTarget {
+        target: Span,
+        operator: Token,
+        message: &'static str,
+    },
 }
 
 impl fmt::Debug for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let found = match self {
+            ParseError::Lex(error) => return write!(f, "{error:?}"),
+            _ => self.found().expect("Only lexical errors have no token"),
+        };
+
+        write!(
+            f,
+            "[line {}:{}] ParseError: {}",
+            found.line,
+            found.column,
+            self.message()
+        )?;
+
         match self {
-            ParseError::ExpectedExpression(token) => {
-                write!(
-                    f,
-                    "[line {}] ParseError: Expected expression, but found token {:#?}",
-                    token.line, token.ty
-                )
+            ParseError::ExpectedExpression(_)
+            | ParseError::ExpectedPattern(_)
+            | ParseError::TokenMismatch { .. } => {
+                write!(f, ", but found {}", found.describe())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ParseError {
+    /// Returns the line where the error was found.
+    #[cfg(test)]
+    pub fn line(&self) -> usize {
+        match self {
+            ParseError::Lex(error) => error.line,
+            _ => self.found().map_or(0, |found| found.line),
+        }
+    }
+
+    /// Returns the token where the error was found, unless it was found while
+    /// scanning.
+    fn found(&self) -> Option<&Token> {
+        match self {
+            ParseError::Lex(_) => None,
+            ParseError::ExpectedExpression(found)
+            | ParseError::ExpectedPattern(found)
+            | ParseError::TokenMismatch { found, .. }
+            | ParseError::InvalidStatement { found, .. }
+            | ParseError::InvalidTarget {
+                operator: found, ..
+            } => Some(found),
+        }
+    }
+
+    /// Returns the error message without its location.
+    fn message(&self) -> String {
+        match self {
+            ParseError::Lex(error) => error.message(),
+            ParseError::ExpectedExpression(_) => String::from("Expected expression"),
+            ParseError::ExpectedPattern(_) => String::from("Expected pattern"),
+            ParseError::TokenMismatch { message, .. }
+            | ParseError::InvalidStatement { message, .. } => message.clone(),
+            ParseError::InvalidTarget { message, .. } => message.to_string(),
+        }
+    }
+
+    /// Creates a diagnostic pointing at the source of the error.
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            ParseError::Lex(error) => error.diagnostic(),
+            ParseError::ExpectedExpression(found) => {
+                Diagnostic::new(SYNTAX_ERROR, &self.message(), found.span)
+                    .with_label(&format!("found {}", found.describe()))
+            }
+            ParseError::ExpectedPattern(found) => {
+                Diagnostic::new(SYNTAX_ERROR, &self.message(), found.span)
+                    .with_label(&format!("found {}", found.describe()))
+                    .with_note("patterns are literals, ranges like 1..10, or '_'")
             }
             ParseError::TokenMismatch {
                 expected,
                 found,
+                after,
                 message,
             } => {
-                write!(
-                    f,
-                    "[line {}] ParseError: Expected token {:#?} but found {:#?}: {}",
-                    found.line, expected, found.ty, message
-                )
+                if *expected == TokenType::Semicolon {
+                    Diagnostic::new(SYNTAX_ERROR, message, Span::new(*after, *after))
+                        .with_label(&format!("expected ';', found {}", found.describe()))
+                        .with_help("statements end with ';'")
+                } else {
+                    Diagnostic::new(SYNTAX_ERROR, message, found.span)
+                        .with_label(&format!("found {}", found.describe()))
+                }
+            }
+            ParseError::InvalidStatement { found, message } => {
+                Diagnostic::new(SYNTAX_ERROR, message, found.span)
             }
+            ParseError::InvalidTarget {
+                target, message, ..
+            } => Diagnostic::new(SYNTAX_ERROR, message, *target),
         }
     }
 }
@@ -0,0 +1,506 @@
+mod resolve_error;
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::internal::ast::{Expr, ExprKind, Param, ParamKind, Stmt, StmtKind};
+use crate::internal::token::Token;
+use resolve_error::ResolveError;
+
+/// A static pass that binds every variable to the scope it lives in.
+///
+/// Chonk has no declarations, so the first assignment to a name that is not
+/// visible from the current scope defines it in the current scope. Names that
+/// are never defined in a local scope are left unresolved and looked up in the
+/// global scope at runtime.
+///
+/// A function body is resolved when the enclosing function or the whole script
+/// has been resolved, so that it sees the variables assigned after it.
+#[derive(Default)]
+pub struct Resolver<'a> {
+    // Names defined in the global scope so far
+    globals: HashSet<String>,
+    // Local scopes from the outermost to the innermost
+    scopes: Vec<Scope<'a>>,
+    // Number of functions enclosing the current statement
+    function_depth: usize,
+    // Functions defined in the global scope, which are resolved at the end of
+    // the script
+    functions: Vec<Function<'a>>,
+}
+
+#[derive(Default)]
+struct Scope<'a> {
+    // The value is `false` while the variable's first value is being evaluated
+    names: HashMap<String, bool>,
+    // A loop scope only holds the loop variable
+    is_loop: bool,
+    // Functions defined in the scope, which are resolved when the function
+    // owning the scope has been resolved
+    functions: Vec<Function<'a>>,
+}
+
+struct Function<'a> {
+    params: &'a mut [Param],
+    body: &'a mut [Stmt],
+    // Names of the loop scopes between the function and the scope holding it,
+    // from the outermost to the innermost
+    blocks: Vec<HashMap<String, bool>>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Creates a new `Resolver` that knows about the given global names.
+    pub fn new(globals: HashSet<String>) -> Self {
+        Self {
+            globals,
+            ..Default::default()
+        }
+    }
+
+    /// Resolves a script, including the bodies of the functions it defines.
+    pub fn resolve(&mut self, statements: &'a mut [Stmt]) -> Result<(), ResolveError> {
+        self.resolve_stmts(statements)?;
+        let functions = std::mem::take(&mut self.functions);
+        self.resolve_functions(functions)
+    }
+
+    /// Resolves a list of statements.
+    fn resolve_stmts(&mut self, statements: &'a mut [Stmt]) -> Result<(), ResolveError> {
+        for stmt in statements {
+            self.resolve_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves statement.
+    fn resolve_stmt(&mut self, stmt: &'a mut Stmt) -> Result<(), ResolveError> {
+        match &mut stmt.kind {
+            StmtKind::Function { name, params, body } => {
+                // Define the name first so that the function can call itself
+                self.define(&name.lexeme);
+                self.function(
+                    Rc::make_mut(params).as_mut_slice(),
+                    Rc::make_mut(body).as_mut_slice(),
+                )?;
+            }
+            StmtKind::While { test, body, .. } => {
+                self.resolve_expr(test)?;
+                self.resolve_stmts(body)?;
+            }
+            StmtKind::For {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.resolve_expr(iterable)?;
+                self.scopes.push(Scope {
+                    is_loop: true,
+                    ..Default::default()
+                });
+                self.define(&name.lexeme);
+                let result = self.resolve_stmts(body);
+                self.end_block(result)?;
+            }
+            StmtKind::If {
+                test,
+                body,
+                or_else,
+            } => {
+                self.resolve_expr(test)?;
+                self.resolve_stmts(body)?;
+                if let Some(else_stmt) = or_else {
+                    self.resolve_stmts(else_stmt)?;
+                }
+            }
+            StmtKind::Break { .. } | StmtKind::Continue { .. } => {}
+            StmtKind::Return { keyword, value } => {
+                if self.function_depth == 0 {
+                    return Err(ResolveError::new(
+                        keyword.clone(),
+                        "Cannot use \"return\" outside function",
+                    ));
+                }
+
+                if let Some(expr) = value {
+                    self.resolve_expr(expr)?;
+                }
+            }
+            StmtKind::Delete(targets) => {
+                for target in targets {
+                    self.resolve_expr(target)?;
+                }
+            }
+            StmtKind::Expr(expr) | StmtKind::Echo(expr) => self.resolve_expr(expr)?,
+            StmtKind::Throw { value, .. } => self.resolve_expr(value)?,
+            StmtKind::Match { subject, arms, .. } => {
+                self.resolve_expr(subject)?;
+                for arm in arms {
+                    self.resolve_stmts(&mut arm.body)?;
+                }
+            }
+            StmtKind::Assert { test, message, .. } => {
+                self.resolve_expr(test)?;
+                if let Some(message) = message {
+                    self.resolve_expr(message)?;
+                }
+            }
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.resolve_stmts(body)?;
+                if let Some(catch) = catch {
+                    catch.depth = self.resolve_assignment(&catch.name, None)?;
+                    self.resolve_stmts(&mut catch.body)?;
+                }
+                if let Some(finally) = finally {
+                    self.resolve_stmts(finally)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves expression.
+    fn resolve_expr(&mut self, expr: &'a mut Expr) -> Result<(), ResolveError> {
+        match &mut expr.kind {
+            ExprKind::Binary(lhs, _, rhs) | ExprKind::Logical(lhs, _, rhs) => {
+                self.resolve_expr(lhs)?;
+                self.resolve_expr(rhs)?;
+            }
+            ExprKind::Unary(_, e) | ExprKind::Grouping(e) => self.resolve_expr(e)?,
+            ExprKind::Conditional {
+                test,
+                then,
+                or_else,
+            } => {
+                self.resolve_expr(test)?;
+                self.resolve_expr(then)?;
+                self.resolve_expr(or_else)?;
+            }
+            ExprKind::Assign { name, value, depth } => {
+                *depth = self.resolve_assignment(name, Some(value))?;
+            }
+            ExprKind::Call(callee, _, arguments) => {
+                self.resolve_expr(callee)?;
+                for arg in arguments {
+                    self.resolve_expr(arg)?;
+                }
+            }
+            ExprKind::Constant(_) => {}
+            ExprKind::Variable { name, depth } => {
+                *depth = self.resolve_local(&name.lexeme);
+
+                let scope = depth.map(|depth| &self.scopes[self.scopes.len() - 1 - depth]);
+                if scope.is_some_and(|scope| !scope.names[&name.lexeme]) {
+                    return Err(ResolveError::new(
+                        name.clone(),
+                        &format!(
+                            "Cannot read local variable \"{}\" in its own initializer",
+                            name.lexeme
+                        ),
+                    ));
+                }
+            }
+            ExprKind::Interpolation(parts) | ExprKind::List(parts) => {
+                for part in parts {
+                    self.resolve_expr(part)?;
+                }
+            }
+            ExprKind::Lambda { params, body, .. } => self.function(
+                Rc::make_mut(params).as_mut_slice(),
+                Rc::make_mut(body).as_mut_slice(),
+            )?,
+            ExprKind::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.resolve_expr(key)?;
+                    self.resolve_expr(value)?;
+                }
+            }
+            ExprKind::Index { object, index, .. } => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)?;
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)?;
+                self.resolve_expr(value)?;
+            }
+            ExprKind::AugAssign {
+                name, value, depth, ..
+            } => {
+                self.resolve_expr(value)?;
+                *depth = self.resolve_local(&name.lexeme);
+            }
+            ExprKind::Prefix { name, depth, .. } => *depth = self.resolve_local(&name.lexeme),
+        }
+
+        Ok(())
+    }
+
+    /// Resolves a function defined in the current scope. Functions defined in
+    /// a local scope are resolved when the scope ends.
+    fn function(
+        &mut self,
+        params: &'a mut [Param],
+        body: &'a mut [Stmt],
+    ) -> Result<(), ResolveError> {
+        let function = Function {
+            params,
+            body,
+            blocks: Vec::new(),
+        };
+        match self.scopes.last_mut() {
+            Some(scope) => scope.functions.push(function),
+            None => self.functions.push(function),
+        }
+        Ok(())
+    }
+
+    /// Resolves the parameters and body of a function in a new scope.
+    fn resolve_function(
+        &mut self,
+        params: &'a mut [Param],
+        body: &'a mut [Stmt],
+    ) -> Result<(), ResolveError> {
+        self.function_depth += 1;
+        self.scopes.push(Scope::default());
+
+        let result = self
+            .resolve_params(params)
+            .and_then(|()| self.resolve_stmts(body));
+        let result = self.end_scope(result);
+        self.function_depth -= 1;
+        result
+    }
+
+    /// Removes the scope of a loop. Its functions are handed over to the
+    /// enclosing scope, or to the global scope if there is none.
+    fn end_block(&mut self, result: Result<(), ResolveError>) -> Result<(), ResolveError> {
+        let block = self.scopes.pop().expect("Loop scope was pushed");
+        let outer = match self.scopes.last_mut() {
+            Some(scope) => &mut scope.functions,
+            None => &mut self.functions,
+        };
+        for mut function in block.functions {
+            function.blocks.insert(0, block.names.clone());
+            outer.push(function);
+        }
+        result
+    }
+
+    /// Resolves the functions defined in the innermost scope, unless resolving
+    /// the scope itself failed, and removes the scope.
+    fn end_scope(&mut self, result: Result<(), ResolveError>) -> Result<(), ResolveError> {
+        let functions = self
+            .scopes
+            .last_mut()
+            .map(|scope| std::mem::take(&mut scope.functions))
+            .unwrap_or_default();
+
+        let result = result.and_then(|()| self.resolve_functions(functions));
+        self.scopes.pop();
+        result
+    }
+
+    /// Resolves functions whose definitions have been resolved.
+    fn resolve_functions(&mut self, functions: Vec<Function<'a>>) -> Result<(), ResolveError> {
+        functions.into_iter().try_for_each(|function| {
+            // Put back the loop scopes the function was defined in
+            let blocks = function.blocks.len();
+            self.scopes
+                .extend(function.blocks.into_iter().map(|names| Scope {
+                    names,
+                    is_loop: true,
+                    functions: Vec::new(),
+                }));
+            let result = self.resolve_function(function.params, function.body);
+            self.scopes.truncate(self.scopes.len() - blocks);
+            result
+        })
+    }
+
+    /// Defines the parameters of a function in its scope. A default value can
+    /// use the parameters before it.
+    fn resolve_params(&mut self, params: &'a mut [Param]) -> Result<(), ResolveError> {
+        for param in params {
+            if let ParamKind::Optional(default) = &mut param.kind {
+                self.declare(&param.name.lexeme);
+                self.resolve_expr(default)?;
+            }
+            self.define(&param.name.lexeme);
+        }
+        Ok(())
+    }
+
+    /// Marks a name as being defined in the innermost scope.
+    fn declare(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.names.insert(name.to_string(), false);
+        }
+    }
+
+    /// Defines a name in the innermost scope.
+    fn define(&mut self, name: &str) {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                scope.names.insert(name.to_string(), true);
+            }
+            None => {
+                self.globals.insert(name.to_string());
+            }
+        }
+    }
+
+    /// Resolves an assignment to a name and its value, if it has one. Returns
+    /// the depth of the assigned variable.
+    fn resolve_assignment(
+        &mut self,
+        name: &Token,
+        value: Option<&'a mut Expr>,
+    ) -> Result<Option<usize>, ResolveError> {
+        let depth = self.resolve_local(&name.lexeme);
+        if depth.is_some() || self.globals.contains(&name.lexeme) {
+            if let Some(value) = value {
+                self.resolve_expr(value)?;
+            }
+            return Ok(depth);
+        }
+
+        // The first assignment defines a new variable in the innermost
+        // function, skipping loop scopes, or in the global scope
+        let Some(i) = self.scopes.iter().rposition(|scope| !scope.is_loop) else {
+            if let Some(value) = value {
+                self.resolve_expr(value)?;
+            }
+            self.globals.insert(name.lexeme.clone());
+            return Ok(None);
+        };
+
+        self.scopes[i].names.insert(name.lexeme.clone(), false);
+        let result = value.map_or(Ok(()), |value| self.resolve_expr(value));
+        self.scopes[i].names.insert(name.lexeme.clone(), true);
+        result?;
+        Ok(Some(self.scopes.len() - 1 - i))
+    }
+
+    /// Returns the number of scopes between the innermost scope and the local
+    /// scope where the name is defined.
+    fn resolve_local(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.names.contains_key(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::parser::Parser;
+
+    #[test]
+    fn test_resolve() {
+        let input = "\
+            total = 0;
+            func make() {
+                n = 0;
+                func inc() {
+                    n += 1;
+                    total += n;
+                    return n;
+                }
+                return inc;
+            }
+        ";
+
+        let mut statements = Parser::new(input).parse().unwrap();
+        assert!(Resolver::default().resolve(&mut statements).is_ok());
+
+        let StmtKind::Function { body, .. } = &statements[1].kind else {
+            panic!("Expected function");
+        };
+        let StmtKind::Function { body, .. } = &body[1].kind else {
+            panic!("Expected function");
+        };
+        let depths: Vec<Option<usize>> = body
+            .iter()
+            .filter_map(|stmt| match &stmt.kind {
+                StmtKind::Expr(Expr {
+                    kind: ExprKind::AugAssign { depth, .. },
+                    ..
+                }) => Some(*depth),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(depths, [Some(1), None]);
+
+        let mut statements = Parser::new("func f() { a = a + 1; }").parse().unwrap();
+        assert!(Resolver::default().resolve(&mut statements).is_err());
+    }
+
+    #[test]
+    fn test_resolve_later_definitions() {
+        let input = "\
+            func outer() {
+                func get() { return n; }
+                func call() { return later(); }
+                n = 1;
+                func later() { return 1; }
+            }
+        ";
+
+        let mut statements = Parser::new(input).parse().unwrap();
+        assert!(Resolver::default().resolve(&mut statements).is_ok());
+
+        let StmtKind::Function { body, .. } = &statements[0].kind else {
+            panic!("Expected function");
+        };
+        let depths: Vec<Option<usize>> = body[..2]
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Function { body, .. } => match &body[0].kind {
+                    StmtKind::Return {
+                        value: Some(value), ..
+                    } => match &value.kind {
+                        ExprKind::Variable { depth, .. } => *depth,
+                        ExprKind::Call(callee, ..) => match &callee.kind {
+                            ExprKind::Variable { depth, .. } => *depth,
+                            _ => panic!("Expected variable"),
+                        },
+                        _ => panic!("Expected variable or call"),
+                    },
+                    _ => panic!("Expected return"),
+                },
+                _ => panic!("Expected function"),
+            })
+            .collect();
+        assert_eq!(depths, [Some(1), Some(1)]);
+
+        // A top-level function also sees the globals assigned after it
+        let input = "func inner() { x = 1; } x = 5;";
+        let mut statements = Parser::new(input).parse().unwrap();
+        assert!(Resolver::default().resolve(&mut statements).is_ok());
+
+        let StmtKind::Function { body, .. } = &statements[0].kind else {
+            panic!("Expected function");
+        };
+        let StmtKind::Expr(Expr {
+            kind: ExprKind::Assign { depth, .. },
+            ..
+        }) = &body[0].kind
+        else {
+            panic!("Expected assignment");
+        };
+        assert_eq!(*depth, None);
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+
+use crate::internal::diagnostic::Diagnostic;
+use crate::internal::token::Token;
+
+pub struct ResolveError {
+    token: Token,
+    message: String,
+}
+
+impl fmt::Debug for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}:{}] ResolveError: {}",
+            self.token.line, self.token.column, self.message
+        )
+    }
+}
+
+impl ResolveError {
+    /// Creates a new `ResolveError`.
+    pub fn new(token: Token, message: &str) -> Self {
+        Self {
+            token,
+            message: message.to_string(),
+        }
+    }
+
+    /// Creates a diagnostic pointing at the token that caused the error.
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new("ResolveError", &self.message, self.token.span)
+    }
+}
@@ -0,0 +1,739 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::iter::zip;
+use std::ops::RangeInclusive;
+use std::process;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod map;
+mod runtime_error;
+mod value;
+
+use crate::internal::ast::{Param, ParamKind, Pattern, PatternKind};
+use crate::internal::token::{Literal, Token, TokenType};
+pub use map::{Key, Map};
+pub use runtime_error::{RuntimeError, StackFrame};
+use value::Address;
+pub use value::{Exception, NativeFunction, Value};
+
+/// The largest number of function calls that can be in progress at once.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// The size of the native stack that programs run on. The interpreter recurses
+/// for every function call, so it needs a large stack to reach
+/// `MAX_CALL_DEPTH`.
+pub const STACK_SIZE: usize = 1 << 30;
+
+/// A handle to a scope of variables. Scopes are shared by reference, so
+/// closures see and change the variables they capture.
+#[derive(Default, Clone)]
+pub struct Environment {
+    scope: Rc<RefCell<Scope>>,
+}
+
+#[derive(Default)]
+struct Scope {
+    store: HashMap<String, Value>,
+    outer: Option<Environment>,
+}
+
+impl Environment {
+    /// Creates a new scope inside the given scope.
+    pub fn new_outer(outer: &Environment) -> Self {
+        Self {
+            scope: Rc::new(RefCell::new(Scope {
+                outer: Some(outer.clone()),
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Returns the scope the given number of levels outside this one.
+    pub fn ancestor(&self, depth: usize) -> Environment {
+        let mut environment = self.clone();
+        for _ in 0..depth {
+            let outer = environment.scope.borrow().outer.clone();
+            environment = outer.expect("Resolved depth exceeds scope nesting");
+        }
+        environment
+    }
+
+    /// Returns `true` if both handles refer to the same scope.
+    pub fn ptr_eq(&self, other: &Environment) -> bool {
+        Rc::ptr_eq(&self.scope, &other.scope)
+    }
+
+    /// Returns the names bound in this scope.
+    pub fn names(&self) -> HashSet<String> {
+        self.scope.borrow().store.keys().cloned().collect()
+    }
+
+    /// Returns the value bound to the name, if any.
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        self.scope.borrow().store.get(name).cloned()
+    }
+
+    /// Returns the value bound to the name.
+    pub fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
+        match self.lookup(&name.lexeme) {
+            Some(value) => Ok(value),
+            None => Err(RuntimeError::new(
+                name.clone(),
+                &format!("Undefined variable \"{}\"", name.lexeme),
+            )),
+        }
+    }
+
+    /// Binds a new name to a value. If the name exists, it assigns a new value
+    /// to it.
+    pub fn set(&self, name: &str, value: &Value) {
+        self.scope
+            .borrow_mut()
+            .store
+            .insert(name.to_string(), value.clone());
+    }
+
+    /// Removes a name-value pair.
+    pub fn pop(&self, name: &Token) -> Result<(), RuntimeError> {
+        match self.scope.borrow_mut().store.remove(&name.lexeme) {
+            Some(_) => Ok(()),
+            None => Err(RuntimeError::new(
+                name.clone(),
+                &format!("Undefined variable \"{}\"", name.lexeme),
+            )),
+        }
+    }
+}
+
+/// The largest number of values the "range" native function produces.
+const MAX_RANGE_LENGTH: usize = 10_000_000;
+
+/// Creates the global scope with the native functions defined in it.
+pub fn globals() -> Environment {
+    let globals = Environment::default();
+
+    globals.set(
+        "clock",
+        &Value::NativeFunction(NativeFunction {
+            name: String::from("clock"),
+            arity: 0..=0,
+            callable: |_, _| match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(n) => Ok(Value::Number(n.as_secs_f64())),
+                Err(_) => panic!("Time went backwards!"),
+            },
+        }),
+    );
+
+    globals.set(
+        "len",
+        &Value::NativeFunction(NativeFunction {
+            name: String::from("len"),
+            arity: 1..=1,
+            callable: |paren, args| match &args[0] {
+                Value::String(s) => Ok(Value::Number(s.chars().count() as f64)),
+                Value::List(list) => Ok(Value::Number(list.borrow().len() as f64)),
+                Value::Map(map) => Ok(Value::Number(map.borrow().len() as f64)),
+                _ => Err(RuntimeError::new(
+                    paren.clone(),
+                    "Argument of \"len\" must be a string, a list or a map",
+                )),
+            },
+        }),
+    );
+
+    globals.set(
+        "range",
+        &Value::NativeFunction(NativeFunction {
+            name: String::from("range"),
+            arity: 1..=3,
+            callable: |paren, args| {
+                let mut bounds: Vec<f64> = Vec::new();
+                for arg in args {
+                    match arg {
+                        Value::Number(n) => bounds.push(*n),
+                        _ => {
+                            return Err(RuntimeError::new(
+                                paren.clone(),
+                                "Arguments of \"range\" must be numbers",
+                            ))
+                        }
+                    }
+                }
+
+                let (start, end, step) = match bounds[..] {
+                    [end] => (0.0, end, 1.0),
+                    [start, end] => (start, end, 1.0),
+                    [start, end, step] => (start, end, step),
+                    _ => unreachable!(),
+                };
+                if !bounds.iter().all(|n| n.is_finite()) {
+                    return Err(RuntimeError::new(
+                        paren.clone(),
+                        "Arguments of \"range\" must be finite",
+                    ));
+                }
+                if step == 0.0 {
+                    return Err(RuntimeError::new(
+                        paren.clone(),
+                        "Step of \"range\" cannot be zero",
+                    ));
+                }
+
+                // Counting first keeps steps too small to change a large
+                // number from looping forever
+                let count = ((end - start) / step).ceil().max(0.0);
+                if count > MAX_RANGE_LENGTH as f64 {
+                    return Err(RuntimeError::new(
+                        paren.clone(),
+                        &format!("\"range\" cannot produce more than {MAX_RANGE_LENGTH} numbers"),
+                    ));
+                }
+
+                let values = (0..count as usize)
+                    .map(|i| Value::Number(start + i as f64 * step))
+                    .collect();
+                Ok(Value::List(Rc::new(RefCell::new(values))))
+            },
+        }),
+    );
+
+    globals.set(
+        "exit",
+        &Value::NativeFunction(NativeFunction {
+            name: String::from("exit"),
+            arity: 0..=1,
+            callable: |paren, args| {
+                let code = match args.first() {
+                    None => 0,
+                    // Statuses wrap around modulo 256, so larger ones would be
+                    // reported as a different status
+                    Some(Value::Number(n)) if n.fract() == 0.0 && (0.0..=255.0).contains(n) => {
+                        *n as i32
+                    }
+                    Some(_) => {
+                        return Err(RuntimeError::new(
+                            paren.clone(),
+                            "Argument of \"exit\" must be an integer from 0 to 255",
+                        ))
+                    }
+                };
+
+                // Output written by the program must not be lost
+                io::stdout().flush().ok();
+                process::exit(code)
+            },
+        }),
+    );
+
+    globals
+}
+
+/// Returns the range of the number of arguments accepted by a Chonk function
+/// with the given parameters. A rest parameter accepts any number of extra
+/// arguments, which is represented by an upper bound of `usize::MAX`.
+pub fn arity(params: &[Param]) -> RangeInclusive<usize> {
+    let count = |f: fn(&ParamKind) -> bool| params.iter().filter(|param| f(&param.kind)).count();
+
+    let required = count(|kind| matches!(kind, ParamKind::Required));
+    if params
+        .last()
+        .is_some_and(|param| param.kind == ParamKind::Rest)
+    {
+        required..=usize::MAX
+    } else {
+        required..=count(|_| true)
+    }
+}
+
+/// Binds the arguments of a call to the parameters of a Chonk function in the
+/// scope of the call, collecting the extra arguments into a list for a rest
+/// parameter. Returns the number of parameters bound to an argument, after
+/// which the parameters take their default values.
+pub fn bind_arguments(params: &[Param], arguments: &[Value], environment: &Environment) -> usize {
+    let mut bound = 0;
+    for (param, argument) in zip(params, arguments) {
+        if param.kind == ParamKind::Rest {
+            break;
+        }
+        environment.set(&param.name.lexeme, argument);
+        bound += 1;
+    }
+
+    if let Some(param) = params.last().filter(|param| param.kind == ParamKind::Rest) {
+        let rest = arguments.get(bound..).unwrap_or_default().to_vec();
+        environment.set(
+            &param.name.lexeme,
+            &Value::List(Rc::new(RefCell::new(rest))),
+        );
+    }
+
+    bound
+}
+
+/// Returns an error if a function accepting the given range of arguments is
+/// called with a different number of them. An upper bound of `usize::MAX`
+/// means there is no limit.
+pub fn check_arity(
+    arity: RangeInclusive<usize>,
+    count: usize,
+    paren: &Token,
+) -> Result<(), RuntimeError> {
+    if arity.contains(&count) {
+        return Ok(());
+    }
+
+    let expected = if arity.start() == arity.end() {
+        arity.start().to_string()
+    } else if *arity.end() == usize::MAX {
+        format!("at least {}", arity.start())
+    } else {
+        format!("{} to {}", arity.start(), arity.end())
+    };
+
+    Err(RuntimeError::new(
+        paren.clone(),
+        &format!("Expected {} arguments but got {}", expected, count),
+    ))
+}
+
+/// Applies a binary operator to two values.
+pub fn binary(left: Value, op: &Token, right: Value) -> Result<Value, RuntimeError> {
+    match (left, op.ty, right) {
+        (Value::Number(n1), TokenType::Greater, Value::Number(n2)) => Ok(Value::Bool(n1 > n2)),
+        (Value::Number(n1), TokenType::GreaterEqual, Value::Number(n2)) => {
+            Ok(Value::Bool(n1 >= n2))
+        }
+        (Value::Number(n1), TokenType::Less, Value::Number(n2)) => Ok(Value::Bool(n1 < n2)),
+        (Value::Number(n1), TokenType::LessEqual, Value::Number(n2)) => Ok(Value::Bool(n1 <= n2)),
+        (left, TokenType::BangEqual, right) => Ok(Value::Bool(left != right)),
+        (left, TokenType::EqEqual, right) => Ok(Value::Bool(left == right)),
+        (
+            left,
+            ty @ (TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual),
+            right,
+        ) => match compare(&left, &right) {
+            Some(ordering) => Ok(Value::Bool(match ty {
+                TokenType::Greater => ordering.is_gt(),
+                TokenType::GreaterEqual => ordering.is_ge(),
+                TokenType::Less => ordering.is_lt(),
+                _ => ordering.is_le(),
+            })),
+            None => Err(RuntimeError::new(
+                op.clone(),
+                &format!(
+                    "Cannot compare {} with {}",
+                    left.type_name(),
+                    right.type_name()
+                ),
+            )),
+        },
+        (Value::Number(n1), TokenType::Minus, Value::Number(n2)) => Ok(Value::Number(n1 - n2)),
+        (Value::Number(n1), TokenType::Plus, Value::Number(n2)) => Ok(Value::Number(n1 + n2)),
+        (Value::String(s1), TokenType::Plus, Value::String(s2)) => Ok(Value::String(s1 + &s2)),
+        (Value::List(l1), TokenType::Plus, Value::List(l2)) => {
+            let mut values = l1.borrow().clone();
+            values.extend(l2.borrow().iter().cloned());
+            Ok(Value::List(Rc::new(RefCell::new(values))))
+        }
+        (Value::Number(n1), TokenType::Percent, Value::Number(n2)) => Ok(Value::Number(n1 % n2)),
+        (Value::Number(n1), TokenType::Slash, Value::Number(n2)) => Ok(Value::Number(n1 / n2)),
+        (Value::Number(n1), TokenType::Star, Value::Number(n2)) => Ok(Value::Number(n1 * n2)),
+        _ => Err(RuntimeError::new(
+            op.clone(),
+            "Invalid operands in binary operator",
+        )),
+    }
+}
+
+/// Creates the error raised by a failed `assert` statement. The values of both
+/// sides of a failed comparison are shown after its source code, and the
+/// message is left out if it is `null`.
+pub fn assertion_error(
+    token: &Token,
+    source: &str,
+    operands: Option<(&Value, &Value)>,
+    message: &Value,
+) -> RuntimeError {
+    let mut text = format!("Assertion failed: {source}");
+    if let Some((left, right)) = operands {
+        text += &format!(" ({} {} {})", left.repr(), token.lexeme, right.repr());
+    }
+    if *message != Value::Null {
+        text += &format!(": {message}");
+    }
+    RuntimeError::new(token.clone(), &text).with_kind("AssertionError")
+}
+
+/// Creates the error raised by a call past `MAX_CALL_DEPTH`.
+pub fn stack_overflow(paren: &Token) -> RuntimeError {
+    RuntimeError::new(paren.clone(), "Stack overflow").with_kind("StackOverflowError")
+}
+
+/// Applies a unary operator to a value.
+pub fn unary(op: &Token, right: &Value) -> Result<Value, RuntimeError> {
+    match (op.ty, right) {
+        (TokenType::Plus, Value::Number(value)) => Ok(Value::Number(*value)),
+        (TokenType::Minus, Value::Number(value)) => Ok(Value::Number(-value)),
+        (TokenType::Bang, _) => Ok(Value::Bool(!is_truthy(right))),
+        _ => Err(RuntimeError::new(
+            op.clone(),
+            "Invalid operand to unary operator",
+        )),
+    }
+}
+
+/// Applies an augmented assignment operator such as `+=` to the current value
+/// of a variable.
+pub fn aug_assign(target: &Value, operator: &Token, rhs: Value) -> Result<Value, RuntimeError> {
+    match (target, operator.ty, rhs) {
+        (Value::Number(n1), TokenType::MinusEqual, Value::Number(n2)) => Ok(Value::Number(n1 - n2)),
+        (Value::Number(n1), TokenType::PlusEqual, Value::Number(n2)) => Ok(Value::Number(n1 + n2)),
+        (Value::String(s1), TokenType::PlusEqual, Value::String(s2)) => {
+            Ok(Value::String(s1.to_owned() + &s2))
+        }
+        (Value::Number(n1), TokenType::PercentEqual, Value::Number(n2)) => {
+            Ok(Value::Number(n1 % n2))
+        }
+        (Value::Number(n1), TokenType::SlashEqual, Value::Number(n2)) => Ok(Value::Number(n1 / n2)),
+        (Value::Number(n1), TokenType::StarEqual, Value::Number(n2)) => Ok(Value::Number(n1 * n2)),
+        _ => Err(RuntimeError::new(
+            operator.clone(),
+            "Invalid value in assignment operator",
+        )),
+    }
+}
+
+/// Applies a prefix increment or decrement operator to the current value of a
+/// variable.
+pub fn prefix(operator: &Token, target: &Value) -> Result<Value, RuntimeError> {
+    match (operator.ty, target) {
+        (TokenType::DoubleMinus, Value::Number(n)) => Ok(Value::Number(n - 1.0)),
+        (TokenType::DoublePlus, Value::Number(n)) => Ok(Value::Number(n + 1.0)),
+        _ => Err(RuntimeError::new(
+            operator.clone(),
+            "Invalid value to prefix operator",
+        )),
+    }
+}
+
+/// Returns `true` if the value is "truthy".
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        _ => true,
+    }
+}
+
+/// Returns the values produced by iterating over a value. Strings produce
+/// their characters, lists their elements and maps their keys.
+pub fn iterate(value: &Value, token: &Token) -> Result<Vec<Value>, RuntimeError> {
+    match value {
+        Value::String(s) => Ok(s.chars().map(|c| Value::String(c.to_string())).collect()),
+        Value::List(list) => Ok(list.borrow().clone()),
+        Value::Map(map) => Ok(map.borrow().iter().map(|(key, _)| key.to_value()).collect()),
+        _ => Err(RuntimeError::new(
+            token.clone(),
+            &format!("Cannot iterate over {}", value.type_name()),
+        )),
+    }
+}
+
+/// Returns the ordering between two values. Numbers are ordered numerically,
+/// strings lexicographically and lists element by element. Any other pair of
+/// values is unordered and returns `None`.
+pub fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    compare_nested(left, right, &mut Vec::new())
+}
+
+/// Orders two values like `compare`. `outer` holds the pairs of lists being
+/// compared around them, and meeting a pair again counts as equal.
+fn compare_nested(
+    left: &Value,
+    right: &Value,
+    outer: &mut Vec<(Address, Address)>,
+) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(n1), Value::Number(n2)) => n1.partial_cmp(n2),
+        (Value::String(s1), Value::String(s2)) => Some(s1.cmp(s2)),
+        (Value::List(l1), Value::List(l2)) => {
+            let pair = (Rc::as_ptr(l1) as Address, Rc::as_ptr(l2) as Address);
+            if outer.contains(&pair) {
+                return Some(Ordering::Equal);
+            }
+
+            outer.push(pair);
+            let (l1, l2) = (l1.borrow(), l2.borrow());
+            let ordering = zip(l1.iter(), l2.iter())
+                .map(|(v1, v2)| compare_nested(v1, v2, outer))
+                .find(|ordering| *ordering != Some(Ordering::Equal))
+                .unwrap_or(Some(l1.len().cmp(&l2.len())));
+            outer.pop();
+            ordering
+        }
+        _ => None,
+    }
+}
+
+/// Returns `true` if a value matches a pattern of a `match` arm. Literals are
+/// compared like `==` does.
+pub fn matches(pattern: &Pattern, value: &Value) -> bool {
+    match &pattern.kind {
+        PatternKind::Literal(literal) => get_value(literal) == *value,
+        PatternKind::Range { start, end } => {
+            compare(value, &get_value(start)).is_some_and(Ordering::is_ge)
+                && compare(value, &get_value(end)).is_some_and(Ordering::is_lt)
+        }
+        PatternKind::Wildcard => true,
+    }
+}
+
+/// Returns the element of a list or the value of a map entry.
+pub fn get_index(target: &Value, key: &Value, bracket: &Token) -> Result<Value, RuntimeError> {
+    match target {
+        Value::List(list) => {
+            let list = list.borrow();
+            let i = list_index(list.len(), key, bracket)?;
+            Ok(list[i].clone())
+        }
+        Value::Map(map) => match map.borrow().get(&map_key(key, bracket)?) {
+            Some(value) => Ok(value.clone()),
+            None => Err(RuntimeError::new(
+                bracket.clone(),
+                &format!("Key {} not found in map", key.repr()),
+            )),
+        },
+        Value::Error(error) => match key {
+            Value::String(name) => error.field(name).ok_or_else(|| {
+                RuntimeError::new(
+                    bracket.clone(),
+                    &format!("Errors have no field {}", key.repr()),
+                )
+            }),
+            _ => Err(RuntimeError::new(
+                bracket.clone(),
+                "Error fields must be strings",
+            )),
+        },
+        _ => Err(RuntimeError::new(
+            bracket.clone(),
+            "Only lists, maps and errors can be indexed",
+        )),
+    }
+}
+
+/// Replaces the element of a list or binds a map key to a value.
+pub fn set_index(
+    target: &Value,
+    key: &Value,
+    value: &Value,
+    bracket: &Token,
+) -> Result<(), RuntimeError> {
+    match target {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            let i = list_index(list.len(), key, bracket)?;
+            list[i] = value.clone();
+            Ok(())
+        }
+        Value::Map(map) => {
+            map.borrow_mut()
+                .insert(map_key(key, bracket)?, value.clone());
+            Ok(())
+        }
+        _ => Err(RuntimeError::new(
+            bracket.clone(),
+            "Only lists and maps support index assignment",
+        )),
+    }
+}
+
+/// Removes the element of a list or the entry of a map.
+pub fn delete_index(target: &Value, key: &Value, bracket: &Token) -> Result<(), RuntimeError> {
+    match target {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            let i = list_index(list.len(), key, bracket)?;
+            list.remove(i);
+            Ok(())
+        }
+        Value::Map(map) => match map.borrow_mut().remove(&map_key(key, bracket)?) {
+            Some(_) => Ok(()),
+            None => Err(RuntimeError::new(
+                bracket.clone(),
+                &format!("Key {} not found in map", key.repr()),
+            )),
+        },
+        _ => Err(RuntimeError::new(
+            bracket.clone(),
+            "Only lists and maps support deletion",
+        )),
+    }
+}
+
+/// Converts a value into a map key.
+pub fn map_key(value: &Value, token: &Token) -> Result<Key, RuntimeError> {
+    Key::from_value(value).ok_or_else(|| {
+        RuntimeError::new(
+            token.clone(),
+            "Map keys must be strings, numbers or booleans",
+        )
+    })
+}
+
+/// Converts an index value into a position inside a list of the given length.
+/// Negative indices count from the end of the list.
+fn list_index(len: usize, index: &Value, bracket: &Token) -> Result<usize, RuntimeError> {
+    let n = match index {
+        Value::Number(n) if n.fract() == 0.0 => *n,
+        _ => {
+            return Err(RuntimeError::new(
+                bracket.clone(),
+                "List indices must be integers",
+            ))
+        }
+    };
+
+    let position = if n < 0.0 { len as f64 + n } else { n };
+    if position < 0.0 || position >= len as f64 {
+        return Err(RuntimeError::new(
+            bracket.clone(),
+            &format!("List index {n} out of range for length {len}"),
+        ));
+    }
+
+    Ok(position as usize)
+}
+
+/// Returns value from literal.
+pub fn get_value(literal: &Literal) -> Value {
+    match literal {
+        Literal::Number(n) => Value::Number(*n),
+        Literal::String(s) => Value::String(s.to_owned()),
+        Literal::True => Value::Bool(true),
+        Literal::False => Value::Bool(false),
+        Literal::Null => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paren() -> Token {
+        Token::new(
+            TokenType::RParen,
+            String::from(")"),
+            None,
+            1,
+            1,
+            Default::default(),
+        )
+    }
+
+    #[test]
+    fn test_check_arity() {
+        let paren = paren();
+        let message =
+            |arity, count| check_arity(arity, count, &paren).map_err(|error| format!("{error:?}"));
+
+        assert!(message(1..=1, 1).is_ok());
+        assert!(message(1..=usize::MAX, 300).is_ok());
+        assert!(message(0..=255, 256)
+            .unwrap_err()
+            .ends_with("Expected 0 to 255 arguments but got 256"));
+        assert!(message(0..=2, 3)
+            .unwrap_err()
+            .ends_with("Expected 0 to 2 arguments but got 3"));
+        assert!(message(2..=usize::MAX, 1)
+            .unwrap_err()
+            .ends_with("Expected at least 2 arguments but got 1"));
+    }
+
+    #[test]
+    fn test_range() {
+        let paren = paren();
+        let Some(Value::NativeFunction(range)) = globals().lookup("range") else {
+            panic!("\"range\" is not a native function");
+        };
+        let range = |args: &[f64]| {
+            let args: Vec<Value> = args.iter().map(|n| Value::Number(*n)).collect();
+            match (range.callable)(&paren, &args) {
+                Ok(Value::List(list)) => Ok(list.borrow().len()),
+                Ok(_) => panic!("\"range\" did not return a list"),
+                Err(error) => Err(format!("{error:?}")),
+            }
+        };
+
+        assert_eq!(range(&[1e16, 1e16 + 4.0]), Ok(4));
+        assert_eq!(range(&[5.0, 0.0, -2.0]), Ok(3));
+        assert!(range(&[0.0, f64::INFINITY])
+            .unwrap_err()
+            .ends_with("Arguments of \"range\" must be finite"));
+        assert!(range(&[1e12])
+            .unwrap_err()
+            .ends_with("\"range\" cannot produce more than 10000000 numbers"));
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let paren = paren();
+        let Some(Value::NativeFunction(exit)) = globals().lookup("exit") else {
+            panic!("\"exit\" is not a native function");
+        };
+
+        // Only invalid statuses return instead of ending the process
+        for status in [256.0, -1.0, 1.5] {
+            let Err(error) = (exit.callable)(&paren, &[Value::Number(status)]) else {
+                panic!("Expected an error for status {status}");
+            };
+            assert!(format!("{error:?}")
+                .ends_with("Argument of \"exit\" must be an integer from 0 to 255"));
+        }
+    }
+
+    #[test]
+    fn test_equality() {
+        let list = |values: Vec<Value>| Value::List(Rc::new(RefCell::new(values)));
+
+        assert!(Value::String(String::from("a")) == Value::String(String::from("a")));
+        assert!(Value::Bool(true) != Value::Bool(false));
+        assert!(Value::Number(1.0) != Value::Null);
+        assert!(Value::Number(1.0) != Value::String(String::from("1")));
+        assert!(list(vec![Value::Number(1.0)]) == list(vec![Value::Number(1.0)]));
+        assert_eq!(
+            compare(
+                &Value::String(String::from("abc")),
+                &Value::String(String::from("abd"))
+            ),
+            Some(Ordering::Less)
+        );
+        assert_eq!(compare(&Value::Bool(true), &Value::Number(1.0)), None);
+    }
+
+    #[test]
+    fn test_cyclic_values() {
+        let cyclic = || {
+            let list = Rc::new(RefCell::new(vec![Value::Number(1.0)]));
+            list.borrow_mut().push(Value::List(Rc::clone(&list)));
+            Value::List(list)
+        };
+        let (a, b) = (cyclic(), cyclic());
+
+        assert_eq!(a.to_string(), "[1, [...]]");
+        assert!(a == b);
+        assert_eq!(compare(&a, &b), Some(Ordering::Equal));
+
+        let map = Rc::new(RefCell::new(Map::default()));
+        let value = Value::Map(Rc::clone(&map));
+        map.borrow_mut()
+            .insert(Key::String(String::from("self")), value.clone());
+        assert_eq!(value.to_string(), "{\"self\": {...}}");
+    }
+}
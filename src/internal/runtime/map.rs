@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::Value;
+
+/// A hashable map key. Only strings, numbers and booleans can be used as keys.
+#[derive(Eq, PartialEq, Hash, Debug, Clone)]
+pub enum Key {
+    Number(u64),
+    String(String),
+    Bool(bool),
+}
+
+impl Key {
+    /// Creates a key from a value. Returns `None` if the value is not hashable.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            // Normalize negative zero so that `0` and `-0` are the same key
+            Value::Number(n) if *n == 0.0 => Some(Key::Number(0f64.to_bits())),
+            Value::Number(n) => Some(Key::Number(n.to_bits())),
+            Value::String(s) => Some(Key::String(s.to_owned())),
+            Value::Bool(b) => Some(Key::Bool(*b)),
+            _ => None,
+        }
+    }
+
+    /// Converts the key back to a value.
+    pub fn to_value(&self) -> Value {
+        match self {
+            Key::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            Key::String(s) => Value::String(s.to_owned()),
+            Key::Bool(b) => Value::Bool(*b),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_value().repr())
+    }
+}
+
+/// A map that remembers the insertion order of its keys.
+#[derive(Default, Clone)]
+pub struct Map {
+    entries: Vec<(Key, Value)>,
+    indices: HashMap<Key, usize>,
+}
+
+impl Map {
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the value bound to the key.
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.indices.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    /// Binds the key to a value. If the key exists, its value is replaced but
+    /// it keeps its original position.
+    pub fn insert(&mut self, key: Key, value: Value) {
+        match self.indices.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    /// Removes the key from the map and returns its value.
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let i = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+
+        // Every entry after the removed one has moved back by one position
+        for (key, _) in &self.entries[i..] {
+            if let Some(index) = self.indices.get_mut(key) {
+                *index -= 1;
+            }
+        }
+
+        Some(value)
+    }
+
+    /// Returns an iterator over the entries in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = &(Key, Value)> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insertion_order() {
+        let mut map = Map::default();
+        for (i, key) in ["c", "a", "b"].into_iter().enumerate() {
+            map.insert(Key::String(key.to_string()), Value::Number(i as f64));
+        }
+        map.insert(Key::String(String::from("c")), Value::Null);
+        map.remove(&Key::String(String::from("a")));
+
+        let keys: Vec<String> = map.iter().map(|(key, _)| key.to_string()).collect();
+        assert_eq!(keys, ["\"c\"", "\"b\""]);
+        assert!(map.get(&Key::String(String::from("b"))).is_some());
+        assert_eq!(
+            Key::from_value(&Value::Number(-0.0)),
+            Key::from_value(&Value::Number(0.0))
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+use std::rc::Rc;
+
+use super::{Exception, Value};
+use crate::internal::diagnostic::Diagnostic;
+use crate::internal::token::Token;
+
+/// Number of identical traceback lines shown before the rest are collapsed.
+const REPEAT_LIMIT: usize = 3;
+
+pub struct RuntimeError {
+    token: Box<Token>,
+    // Name of the error type, which is "Error" for values thrown by Chonk code
+    kind: String,
+    message: String,
+    // Value thrown by Chonk code, or `null` for errors raised by Chonk itself
+    value: Box<Value>,
+    // Function calls in progress when the error occurred, outermost first
+    trace: Vec<StackFrame>,
+}
+
+/// A call of a Chonk function.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StackFrame {
+    pub function: String,
+    // Line of the call site
+    pub line: usize,
+}
+
+impl StackFrame {
+    /// Creates a new `StackFrame` for a call of a function that may be
+    /// anonymous.
+    pub fn new(name: Option<&Token>, paren: &Token) -> Self {
+        Self {
+            function: name.map_or_else(|| String::from("<anonymous>"), |t| t.lexeme.clone()),
+            line: paren.line,
+        }
+    }
+}
+
+impl fmt::Debug for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}:{}] {}: {}",
+            self.token.line, self.token.column, self.kind, self.message
+        )
+    }
+}
+
+impl RuntimeError {
+    /// Creates a new `RuntimeError`.
+    pub fn new(token: Token, message: &str) -> Self {
+        Self {
+            token: Box::new(token),
+            kind: String::from("RuntimeError"),
+            message: message.to_string(),
+            value: Box::new(Value::Null),
+            trace: Vec::new(),
+        }
+    }
+
+    /// Changes the name of the error type.
+    pub fn with_kind(mut self, kind: &str) -> Self {
+        self.kind = kind.to_string();
+        self
+    }
+
+    /// Creates the error raised by a `throw` statement. Throwing a caught
+    /// error raises it again unchanged, including its traceback, while any
+    /// other value becomes the message of a new error that keeps the value.
+    pub fn thrown(keyword: &Token, value: &Value) -> Self {
+        match value {
+            Value::Error(error) => Self {
+                token: Box::new(error.token.clone()),
+                kind: error.kind.clone(),
+                message: error.message.clone(),
+                value: Box::new(error.value.clone()),
+                trace: error.trace.clone(),
+            },
+            _ => Self {
+                token: Box::new(keyword.clone()),
+                kind: String::from("Error"),
+                message: value.to_string(),
+                value: Box::new(value.clone()),
+                trace: Vec::new(),
+            },
+        }
+    }
+
+    /// Converts the error into the value assigned by a `catch` clause.
+    pub fn to_value(&self) -> Value {
+        Value::Error(Rc::new(Exception {
+            kind: self.kind.clone(),
+            message: self.message.clone(),
+            value: (*self.value).clone(),
+            token: (*self.token).clone(),
+            trace: self.trace.clone(),
+        }))
+    }
+
+    /// Attaches the call stack at the point the error occurred. Only the
+    /// innermost call stack is kept as the error propagates through callers.
+    pub fn with_trace(mut self, trace: &[StackFrame]) -> Self {
+        if self.trace.is_empty() {
+            self.trace = trace.to_vec();
+        }
+        self
+    }
+
+    /// Returns the function calls in progress when the error occurred.
+    #[cfg(test)]
+    pub fn trace(&self) -> &[StackFrame] {
+        &self.trace
+    }
+
+    /// Formats the call stack in the style of a Python traceback, or returns
+    /// an empty string if the error occurred outside any function.
+    pub fn traceback(&self, file: &str) -> String {
+        if self.trace.is_empty() {
+            return String::new();
+        }
+
+        // Each call site is inside the function called before it
+        let functions = std::iter::once("<script>")
+            .chain(self.trace.iter().map(|frame| frame.function.as_str()));
+        let lines = self
+            .trace
+            .iter()
+            .map(|frame| frame.line)
+            .chain([self.token.line]);
+
+        let mut output = String::from("Traceback (most recent call last):\n");
+        let mut previous = String::new();
+        let mut repeated = 0;
+        for (function, line) in functions.zip(lines) {
+            let entry = format!("  File \"{file}\", line {line}, in {function}\n");
+            if entry == previous {
+                repeated += 1;
+                if repeated >= REPEAT_LIMIT {
+                    continue;
+                }
+            } else {
+                output.push_str(&collapsed(repeated));
+                repeated = 0;
+            }
+            output.push_str(&entry);
+            previous = entry;
+        }
+        output.push_str(&collapsed(repeated));
+
+        output
+    }
+
+    /// Creates a diagnostic pointing at the token that caused the error.
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(&self.kind, &self.message, self.token.span)
+    }
+}
+
+/// Returns the line that replaces the traceback entries over the limit.
+fn collapsed(repeated: usize) -> String {
+    if repeated < REPEAT_LIMIT {
+        return String::new();
+    }
+    format!(
+        "  [Previous line repeated {} more times]\n",
+        repeated + 1 - REPEAT_LIMIT
+    )
+}
@@ -0,0 +1,219 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::iter::zip;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use super::{Map, RuntimeError, StackFrame};
+use crate::internal::interpreter::ChonkFunction;
+use crate::internal::token::Token;
+use crate::internal::vm::Closure;
+
+#[derive(Clone)]
+pub enum Value {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map>>),
+    NativeFunction(NativeFunction),
+    // Functions of the tree-walk interpreter
+    ChonkFunction(ChonkFunction),
+    // Functions of the bytecode virtual machine
+    Closure(Closure),
+    // An error caught by a `try` statement
+    Error(Rc<Exception>),
+    Null,
+}
+
+/// The address of a list or a map, used to find collections that contain
+/// themselves.
+pub type Address = *const ();
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut Vec::new())
+    }
+}
+
+// Values of different types are never equal. Lists and maps are compared by
+// their contents, while functions are only equal to themselves.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other, &mut Vec::new())
+    }
+}
+
+impl Value {
+    /// Returns the name of the value's type.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::NativeFunction(_) | Value::ChonkFunction(_) | Value::Closure(_) => "function",
+            Value::Error(_) => "error",
+            Value::Null => "null",
+        }
+    }
+
+    /// Returns the representation of the value when it is nested inside a
+    /// collection. Strings are quoted so that `["1"]` and `[1]` look different.
+    pub fn repr(&self) -> String {
+        let mut output = String::new();
+        self.write_nested(&mut output, &mut Vec::new())
+            .expect("Writing to a string cannot fail");
+        output
+    }
+
+    /// Returns the address of the value if it is a list or a map.
+    fn address(&self) -> Option<Address> {
+        match self {
+            Value::List(list) => Some(Rc::as_ptr(list) as Address),
+            Value::Map(map) => Some(Rc::as_ptr(map) as Address),
+            _ => None,
+        }
+    }
+
+    /// Writes the value. `outer` holds the lists and maps being written
+    /// around it, which are shown as `[...]` and `{...}` when they contain
+    /// themselves.
+    fn write(&self, f: &mut impl fmt::Write, outer: &mut Vec<Address>) -> fmt::Result {
+        if self
+            .address()
+            .is_some_and(|address| outer.contains(&address))
+        {
+            return match self {
+                Value::List(_) => write!(f, "[...]"),
+                _ => write!(f, "{{...}}"),
+            };
+        }
+
+        match self {
+            Value::Number(value) => write!(f, "{value}"),
+            Value::String(value) => write!(f, "{value}"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::List(list) => {
+                outer.extend(self.address());
+                write!(f, "[")?;
+                for (i, value) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.write_nested(f, outer)?;
+                }
+                outer.pop();
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                outer.extend(self.address());
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: ")?;
+                    value.write_nested(f, outer)?;
+                }
+                outer.pop();
+                write!(f, "}}")
+            }
+            Value::NativeFunction(func) => write!(f, "{func}"),
+            Value::ChonkFunction(func) => write!(f, "{func}"),
+            Value::Closure(func) => write!(f, "{func}"),
+            Value::Error(error) => write!(f, "{}: {}", error.kind, error.message),
+            Value::Null => write!(f, "null"),
+        }
+    }
+
+    /// Writes the value as it is shown inside a collection.
+    fn write_nested(&self, f: &mut impl fmt::Write, outer: &mut Vec<Address>) -> fmt::Result {
+        match self {
+            Value::String(value) => write!(f, "{value:?}"),
+            _ => self.write(f, outer),
+        }
+    }
+
+    /// Returns `true` if the values are equal. `outer` holds the pairs of
+    /// lists and maps being compared around them. Meeting a pair again means
+    /// that both contain themselves in the same way, so it counts as equal.
+    fn equals(&self, other: &Self, outer: &mut Vec<(Address, Address)>) -> bool {
+        let pair = self.address().zip(other.address());
+        if pair.is_some_and(|pair| pair.0 == pair.1 || outer.contains(&pair)) {
+            return true;
+        }
+
+        outer.extend(pair);
+        let equal = match (self, other) {
+            (Value::Number(n1), Value::Number(n2)) => n1 == n2,
+            (Value::String(s1), Value::String(s2)) => s1 == s2,
+            (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+            (Value::List(l1), Value::List(l2)) => {
+                let (l1, l2) = (l1.borrow(), l2.borrow());
+                l1.len() == l2.len()
+                    && zip(l1.iter(), l2.iter()).all(|(v1, v2)| v1.equals(v2, outer))
+            }
+            // Maps are equal if they have the same entries, regardless of order
+            (Value::Map(m1), Value::Map(m2)) => {
+                let (m1, m2) = (m1.borrow(), m2.borrow());
+                m1.len() == m2.len()
+                    && m1
+                        .iter()
+                        .all(|(key, v1)| m2.get(key).is_some_and(|v2| v1.equals(v2, outer)))
+            }
+            (Value::NativeFunction(f1), Value::NativeFunction(f2)) => f1.name == f2.name,
+            (Value::ChonkFunction(f1), Value::ChonkFunction(f2)) => f1 == f2,
+            (Value::Closure(f1), Value::Closure(f2)) => f1 == f2,
+            (Value::Error(e1), Value::Error(e2)) => Rc::ptr_eq(e1, e2),
+            (Value::Null, Value::Null) => true,
+            _ => false,
+        };
+        if pair.is_some() {
+            outer.pop();
+        }
+        equal
+    }
+}
+
+/// An error as seen by Chonk code. Its fields are read by indexing it, e.g.
+/// `error["message"]`.
+pub struct Exception {
+    pub kind: String,
+    pub message: String,
+    // Value thrown by Chonk code, or `null` for errors raised by Chonk itself
+    pub value: Value,
+    // Token where the error occurred
+    pub token: Token,
+    // Function calls in progress when the error occurred, kept so that
+    // throwing the error again reports where it first happened
+    pub trace: Vec<StackFrame>,
+}
+
+impl Exception {
+    /// Returns the field with the given name.
+    pub fn field(&self, name: &str) -> Option<Value> {
+        match name {
+            "kind" => Some(Value::String(self.kind.clone())),
+            "message" => Some(Value::String(self.message.clone())),
+            "value" => Some(self.value.clone()),
+            "line" => Some(Value::Number(self.token.line as f64)),
+            _ => None,
+        }
+    }
+}
+
+/// A function implemented in Rust. Both backends call it the same way.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: RangeInclusive<usize>,
+    pub callable: fn(&Token, &[Value]) -> Result<Value, RuntimeError>,
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native function {}>", self.name)
+    }
+}
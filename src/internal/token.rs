@@ -11,22 +11,65 @@ pub enum Literal {
     Null,
 }
 
+/// A range of byte offsets in the source code. The end is exclusive.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Creates a new `Span`.
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the span that covers both this span and the other one.
+    pub fn to(self, other: Span) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub struct Token {
     pub ty: TokenType,
     pub lexeme: String,
     pub literal: Option<Literal>,
     pub line: usize,
+    // Counted in characters from the start of the line, starting at 1
+    pub column: usize,
+    pub span: Span,
 }
 
 impl Token {
     /// Creates a new `Token`.
-    pub fn new(ty: TokenType, lexeme: String, literal: Option<Literal>, line: usize) -> Self {
+    pub fn new(
+        ty: TokenType,
+        lexeme: String,
+        literal: Option<Literal>,
+        line: usize,
+        column: usize,
+        span: Span,
+    ) -> Self {
         Self {
             ty,
             lexeme,
             literal,
             line,
+            column,
+            span,
+        }
+    }
+
+    /// Describes the token for error messages.
+    pub fn describe(&self) -> String {
+        if token_type::is_eof(self.ty) {
+            String::from("end of input")
+        } else {
+            format!("\"{}\"", self.lexeme)
         }
     }
 }
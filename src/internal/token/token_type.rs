@@ -9,6 +9,11 @@ pub enum TokenType {
     Number, // 123
     String, // "Hello World"
 
+    // Interpolated string parts
+    FStringStart,  // f"
+    FStringMiddle, // text between interpolations
+    FStringEnd,    // "
+
     // Operators
     Plus,    // +
     Minus,   // -
@@ -24,6 +29,7 @@ pub enum TokenType {
 
     DoubleAmper, // &&
     DoubleVBar,  // ||
+    VBar,        // |
     DoublePlus,  // ++
     DoubleMinus, // --
 
@@ -32,6 +38,7 @@ pub enum TokenType {
     Greater, // >
     Equal,   // =
     Bang,    // !
+    Arrow,   // =>
 
     BangEqual,    // !=
     LessEqual,    // <=
@@ -41,8 +48,14 @@ pub enum TokenType {
     RParen,    // )
     LBrace,    // {
     RBrace,    // }
+    LBracket,  // [
+    RBracket,  // ]
     Comma,     // ,
+    Colon,     // :
     Semicolon, // ;
+    DotDot,    // ..
+    Ellipsis,  // ...
+    Question,  // ?
 
     // Keywords
     Null,
@@ -52,9 +65,32 @@ pub enum TokenType {
     If,
     Else,
     While,
+    For,
+    In,
+    Break,
+    Continue,
     Return,
     Delete,
     Echo,
+    Try,
+    Catch,
+    Finally,
+    Throw,
+    Assert,
+    Match,
+}
+
+/// Returns `true` if the token type is an operator comparing two values.
+pub fn is_comparison(ty: TokenType) -> bool {
+    matches!(
+        ty,
+        TokenType::EqEqual
+            | TokenType::BangEqual
+            | TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+    )
 }
 
 /// Returns `true` if the token type is `Eof`.
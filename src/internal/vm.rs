@@ -0,0 +1,678 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+mod chunk;
+mod compiler;
+
+use crate::internal::ast::Stmt;
+use crate::internal::runtime::{
+    arity, assertion_error, aug_assign, binary, bind_arguments, check_arity, delete_index,
+    get_index, globals, is_truthy, iterate, map_key, matches, prefix, set_index, stack_overflow,
+    unary, Environment, Map, RuntimeError, StackFrame, Value, MAX_CALL_DEPTH,
+};
+use crate::internal::token::Token;
+use chunk::{Function, Op};
+use compiler::Compiler;
+
+/// Chonk virtual machine. It compiles programs to bytecode and runs them on a
+/// stack of values.
+pub struct Vm {
+    is_interactive: bool,
+    globals: Environment,
+    environment: Environment,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    // Exception handlers of the `try` statements being executed, innermost
+    // last
+    handlers: Vec<Handler>,
+}
+
+/// A function call in progress.
+struct CallFrame {
+    function: Rc<Function>,
+    ip: usize,
+    // Height of the stack when the function was called
+    base: usize,
+    // Scope of the caller, restored when the function returns
+    caller: Environment,
+}
+
+/// The state to restore when an error is caught.
+struct Handler {
+    // Number of callers of the function containing the `try` statement
+    frames: usize,
+    ip: usize,
+    stack: usize,
+    environment: Environment,
+}
+
+impl CallFrame {
+    fn token(&self, i: u32) -> &Token {
+        &self.function.chunk.tokens[i as usize]
+    }
+
+    /// Returns a constant that the compiler stored as a string.
+    fn string(&self, i: u32) -> &str {
+        match &self.function.chunk.constants[i as usize] {
+            Value::String(s) => s,
+            value => unreachable!("Expected string constant, found {value}"),
+        }
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        let globals = globals();
+
+        // The top-level scope is the global scope
+        Self {
+            is_interactive: false,
+            environment: globals.clone(),
+            globals,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            handlers: Vec::new(),
+        }
+    }
+}
+
+impl Vm {
+    /// Creates a new `Vm`.
+    pub fn new(is_interactive: bool) -> Self {
+        Self {
+            is_interactive,
+            ..Default::default()
+        }
+    }
+
+    /// Compiles and runs a list of statements. The statements must be resolved
+    /// by a `Resolver` first.
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        let script = CallFrame {
+            function: Rc::new(Compiler::compile(statements)),
+            ip: 0,
+            base: self.stack.len(),
+            caller: self.environment.clone(),
+        };
+
+        let result = self.run(script);
+        if result.is_err() {
+            // Unwind everything the failed program left behind
+            self.stack.clear();
+            self.frames.clear();
+            self.handlers.clear();
+            self.environment = self.globals.clone();
+        }
+        result
+    }
+
+    /// Returns the names of all global variables.
+    pub fn global_names(&self) -> HashSet<String> {
+        self.globals.names()
+    }
+
+    /// Returns the value of a global variable.
+    #[cfg(test)]
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.lookup(name)
+    }
+
+    /// Returns the scope the given number of levels outside the current one,
+    /// or the global scope.
+    fn scope_of(&self, depth: Option<u32>) -> Environment {
+        match depth {
+            Some(depth) => self.environment.ancestor(depth as usize),
+            None => self.globals.clone(),
+        }
+    }
+
+    fn run(&mut self, mut frame: CallFrame) -> Result<(), RuntimeError> {
+        loop {
+            let error = match self.execute(&mut frame) {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            let error = error.with_trace(&self.trace(&frame));
+            let Some(handler) = self.handlers.pop() else {
+                return Err(error);
+            };
+
+            // Unwind to the `try` statement and continue in its handler
+            while self.frames.len() > handler.frames {
+                frame = self.frames.pop().expect("Handler frame must exist");
+            }
+            self.stack.truncate(handler.stack);
+            self.environment = handler.environment;
+            frame.ip = handler.ip;
+            self.stack.push(error.to_value());
+        }
+    }
+
+    /// Returns the Chonk function calls in progress, outermost first.
+    fn trace(&self, frame: &CallFrame) -> Vec<StackFrame> {
+        let frames: Vec<&CallFrame> = self.frames.iter().chain([frame]).collect();
+        frames
+            .windows(2)
+            .map(|pair| {
+                // The caller stopped right after the call instruction
+                let caller = pair[0];
+                let Op::Call { paren, .. } = caller.function.chunk.code[caller.ip - 1] else {
+                    unreachable!("Functions are only entered through calls");
+                };
+                StackFrame::new(pair[1].function.name.as_ref(), caller.token(paren))
+            })
+            .collect()
+    }
+
+    fn execute(&mut self, frame: &mut CallFrame) -> Result<(), RuntimeError> {
+        loop {
+            let op = frame.function.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Constant(i) => {
+                    let value = frame.function.chunk.constants[i as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Null => self.stack.push(Value::Null),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Statement => {
+                    let value = self.pop();
+                    if self.is_interactive {
+                        println!("{}", value);
+                    }
+                }
+                Op::Echo => println!("{}", self.pop()),
+                Op::GetLocal { depth, name } => {
+                    let value = self.scope_of(Some(depth)).get(frame.token(name))?;
+                    self.stack.push(value);
+                }
+                Op::GetGlobal(name) => {
+                    let value = self.globals.get(frame.token(name))?;
+                    self.stack.push(value);
+                }
+                Op::SetLocal { depth, name } => {
+                    self.scope_of(Some(depth))
+                        .set(&frame.token(name).lexeme, self.peek());
+                }
+                Op::SetGlobal(name) => self.globals.set(&frame.token(name).lexeme, self.peek()),
+                Op::Define(name) => {
+                    let value = self.pop();
+                    self.environment.set(&frame.token(name).lexeme, &value);
+                }
+                Op::DeleteLocal { depth, name } => {
+                    self.scope_of(Some(depth)).pop(frame.token(name))?
+                }
+                Op::DeleteGlobal(name) => self.globals.pop(frame.token(name))?,
+                Op::Binary(op) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(binary(left, frame.token(op), right)?);
+                }
+                Op::Unary(op) => {
+                    let right = self.pop();
+                    self.stack.push(unary(frame.token(op), &right)?);
+                }
+                Op::AugAssign(operator) => {
+                    let rhs = self.pop();
+                    let target = self.pop();
+                    self.stack
+                        .push(aug_assign(&target, frame.token(operator), rhs)?);
+                }
+                Op::Prefix(operator) => {
+                    let target = self.pop();
+                    self.stack.push(prefix(frame.token(operator), &target)?);
+                }
+                Op::Jump(target) => frame.ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if !is_truthy(self.peek()) {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::JumpIfTrue(target) => {
+                    if is_truthy(self.peek()) {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::Call { argc, paren } => {
+                    let paren = frame.token(paren).clone();
+                    let args = self.stack.split_off(self.stack.len() - argc as usize);
+                    let callee = self.pop();
+
+                    match callee {
+                        Value::NativeFunction(func) => {
+                            check_arity(func.arity.clone(), args.len(), &paren)?;
+                            self.stack.push((func.callable)(&paren, &args)?);
+                        }
+                        Value::Closure(closure) => {
+                            check_arity(arity(&closure.function.params), args.len(), &paren)?;
+                            if self.frames.len() >= MAX_CALL_DEPTH {
+                                return Err(stack_overflow(&paren));
+                            }
+
+                            let environment = Environment::new_outer(&closure.environment);
+                            let bound =
+                                bind_arguments(&closure.function.params, &args, &environment);
+
+                            let callee_frame = CallFrame {
+                                ip: closure.function.entry(bound),
+                                function: closure.function,
+                                base: self.stack.len(),
+                                caller: mem::replace(&mut self.environment, environment),
+                            };
+                            self.frames.push(mem::replace(frame, callee_frame));
+                        }
+                        _ => {
+                            return Err(RuntimeError::new(paren, "Can only call functions"));
+                        }
+                    }
+                }
+                Op::Closure(i) => {
+                    let function = Rc::clone(&frame.function.chunk.functions[i as usize]);
+                    self.stack.push(Value::Closure(Closure {
+                        function,
+                        environment: self.environment.clone(),
+                    }));
+                }
+                Op::Return => {
+                    let value = self.pop();
+                    self.stack.truncate(frame.base);
+
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            let callee = mem::replace(frame, caller);
+                            self.environment = callee.caller;
+                            self.stack.push(value);
+                        }
+                        None => {
+                            self.environment = frame.caller.clone();
+                            return Ok(());
+                        }
+                    }
+                }
+                Op::List(len) => {
+                    let values = self.stack.split_off(self.stack.len() - len as usize);
+                    self.stack.push(Value::List(Rc::new(RefCell::new(values))));
+                }
+                Op::Map { len, brace } => {
+                    let values = self.stack.split_off(self.stack.len() - 2 * len as usize);
+                    let mut map = Map::default();
+                    for entry in values.chunks(2) {
+                        map.insert(map_key(&entry[0], frame.token(brace))?, entry[1].clone());
+                    }
+                    self.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+                }
+                Op::Interpolation(len) => {
+                    let parts = self.stack.split_off(self.stack.len() - len as usize);
+                    let result: String = parts.iter().map(|part| part.to_string()).collect();
+                    self.stack.push(Value::String(result));
+                }
+                Op::Index(bracket) => {
+                    let key = self.pop();
+                    let target = self.pop();
+                    self.stack
+                        .push(get_index(&target, &key, frame.token(bracket))?);
+                }
+                Op::SetIndex(bracket) => {
+                    let value = self.pop();
+                    let key = self.pop();
+                    let target = self.pop();
+                    set_index(&target, &key, &value, frame.token(bracket))?;
+                    self.stack.push(value);
+                }
+                Op::DeleteIndex(bracket) => {
+                    let key = self.pop();
+                    let target = self.pop();
+                    delete_index(&target, &key, frame.token(bracket))?;
+                }
+                Op::PushScope => {
+                    self.environment = Environment::new_outer(&self.environment);
+                }
+                Op::PopScope => self.environment = self.environment.ancestor(1),
+                Op::Iterate(name) => {
+                    let values = iterate(&self.pop(), frame.token(name))?;
+                    self.stack.push(Value::List(Rc::new(RefCell::new(values))));
+                    self.stack.push(Value::Number(0.0));
+                }
+                Op::Compare(op) => {
+                    let len = self.stack.len();
+                    let (left, right) = (self.stack[len - 2].clone(), self.stack[len - 1].clone());
+                    self.stack.push(binary(left, frame.token(op), right)?);
+                }
+                Op::AssertionFailed { keyword, source } => {
+                    let message = self.pop();
+                    return Err(assertion_error(
+                        frame.token(keyword),
+                        frame.string(source),
+                        None,
+                        &message,
+                    ));
+                }
+                Op::ComparisonFailed { operator, source } => {
+                    let message = self.pop();
+                    let right = self.pop();
+                    let left = self.pop();
+                    return Err(assertion_error(
+                        frame.token(operator),
+                        frame.string(source),
+                        Some((&left, &right)),
+                        &message,
+                    ));
+                }
+                Op::Match(pattern) => {
+                    let pattern = &frame.function.chunk.patterns[pattern as usize];
+                    let is_match = matches(pattern, self.peek());
+                    self.stack.push(Value::Bool(is_match));
+                }
+                Op::Throw(keyword) => {
+                    let value = self.pop();
+                    return Err(RuntimeError::thrown(frame.token(keyword), &value));
+                }
+                Op::PushHandler(target) => self.handlers.push(Handler {
+                    frames: self.frames.len(),
+                    ip: target as usize,
+                    stack: self.stack.len(),
+                    environment: self.environment.clone(),
+                }),
+                Op::PopHandler => {
+                    self.handlers.pop();
+                }
+                Op::Next(exit) => {
+                    // The values and the position pushed by `Iterate`
+                    let len = self.stack.len();
+                    let (Value::List(values), Value::Number(position)) =
+                        (&self.stack[len - 2], &self.stack[len - 1])
+                    else {
+                        unreachable!("Iteration state must be on top of the stack");
+                    };
+
+                    let position = *position as usize;
+                    let next = values.borrow().get(position).cloned();
+                    match next {
+                        Some(value) => {
+                            self.stack[len - 1] = Value::Number((position + 1) as f64);
+                            self.stack.push(value);
+                        }
+                        None => frame.ip = exit as usize,
+                    }
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Stack underflow")
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("Stack underflow")
+    }
+}
+
+/// A compiled function together with the scope it was created in.
+#[derive(Clone)]
+pub struct Closure {
+    function: Rc<Function>,
+    environment: Environment,
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function.name {
+            Some(name) => write!(f, "<function {}>", name.lexeme),
+            None => write!(f, "<anonymous function>"),
+        }
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.function, &other.function) && self.environment.ptr_eq(&other.environment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::interpreter::Interpreter;
+    use crate::internal::parser::Parser;
+    use crate::internal::resolver::Resolver;
+
+    /// Parses and resolves the input.
+    fn parse(input: &str, globals: HashSet<String>) -> Vec<Stmt> {
+        let mut statements = Parser::new(input).parse().unwrap();
+        Resolver::new(globals).resolve(&mut statements).unwrap();
+        statements
+    }
+
+    #[test]
+    fn test_same_as_interpreter() {
+        let programs = [
+            "result = [1 + 2 * 3, \"a\" + \"b\", !null, 1 < 2 || x, 7 % 4];",
+            "func fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }
+            result = fib(15);",
+            "result = [];
+            outer: for i in range(4) {
+                for j in range(4) {
+                    if j == 2 { continue outer; }
+                    if i == 3 { break outer; }
+                    result = result + [f\"{i}{j}\"];
+                }
+            }",
+            "func make() {
+                n = 0;
+                return () => ++n;
+            }
+            counter = make();
+            counter();
+            result = counter();",
+            "func make() {
+                func get() { return n; }
+                n = 1;
+                return get;
+            }
+            func outer() {
+                func a() { return b(); }
+                func b() { return 2; }
+                adders = [];
+                for i in range(2) { adders = adders + [() => i + step]; }
+                step = 10;
+                for i in range(2) { last = i; }
+                return [a(), adders[0](), adders[1](), last];
+            }
+            result = [make()(), outer()];",
+            "m = {\"a\": 1, \"b\": [2, 3]};
+            m[\"b\"][-1] = 4;
+            m[\"c\"] = len(m[\"b\"]);
+            del m[\"a\"];
+            result = m;",
+            "i = 0;
+            while true {
+                i += 1;
+                if i % 2 == 0 { continue; }
+                if i > 7 { break; }
+            }
+            result = i;",
+            "func fail(n) {
+                if n == 0 { len(1); }
+                return fail(n - 1);
+            }
+            result = [];
+            try { fail(3); } catch e { result = result + [e[\"message\"], e[\"line\"]]; }
+            try {
+                try { throw \"a\"; } catch e { throw e; } finally { result = result + [1]; }
+            } catch e { result = result + [f\"{e}\"]; }",
+            "func f(xs) {
+                result = [];
+                for x in xs {
+                    try {
+                        if x == 1 { continue; }
+                        if x == 3 { return result; }
+                        result = result + [x];
+                    } finally {
+                        result = result + [-x];
+                    }
+                }
+            }
+            result = f(range(5));",
+            "func f(a, b = a * 2, ...rest) { return [a, b, rest]; }
+            calls = 0;
+            func count(n = ++calls) { return n; }
+            result = [f(1), f(1, 5), f(1, 5, 6, 7), count(), count(), count(9), calls];",
+            "sign = (n) => n > 0 ? 1 : n < 0 ? -1 : 0;
+            total = 0;
+            total += false ? 100 : 1;
+            result = [sign(5), sign(-2), sign(0), total, null ? x : \"lazy\"];",
+            "result = [];
+            for x in [0, 1, 2, 5, 12, \"b\", [], null] {
+                match x {
+                    0 => result = result + [\"zero\"];
+                    1 | 2 => { result = result + [\"small\"]; },
+                    3..10 => {
+                        if x == 5 { continue; }
+                        result = result + [\"medium\"];
+                    }
+                    \"a\" | \"b\" => result = result + [x];
+                    null => break;
+                    _ => result = result + [\"other\"];
+                }
+            }",
+        ];
+
+        for program in programs {
+            let mut interpreter = Interpreter::new(false);
+            let statements = parse(program, interpreter.global_names());
+            interpreter.interpret(&statements).unwrap();
+
+            let mut vm = Vm::new(false);
+            let statements = parse(program, vm.global_names());
+            vm.interpret(&statements).unwrap();
+
+            let expected = interpreter.global("result").unwrap();
+            let result = vm.global("result").unwrap();
+            assert!(result == expected, "{program}: {result} != {expected}");
+        }
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let mut vm = Vm::new(false);
+        let statements = parse("for x in [1] { y = x; z[0]; }", vm.global_names());
+        assert!(vm.interpret(&statements).is_err());
+
+        // The machine is left in a usable state after an error
+        let statements = parse("result = 1;", vm.global_names());
+        assert!(vm.interpret(&statements).is_ok());
+        assert!(vm.stack.is_empty());
+        assert!(vm.environment.ptr_eq(&vm.globals));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut vm = Vm::new(false);
+        let program = "func f(n) { return f(n + 1) + 1; }
+            try { f(0); } catch e { result = e[\"message\"]; }";
+        let statements = parse(program, vm.global_names());
+        assert!(vm.interpret(&statements).is_ok());
+        assert!(vm.global("result") == Some(Value::String(String::from("Stack overflow"))));
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    fn test_assert() {
+        let programs = [
+            (
+                "x = [1]; assert len(x) + 1 == 3;",
+                "len(x) + 1 == 3 (2 == 3)",
+            ),
+            (
+                "x = \"a\"; assert x != \"a\", f\"got {x}\";",
+                "x != \"a\" (\"a\" != \"a\"): got a",
+            ),
+            ("assert !true;", "!true"),
+        ];
+
+        for (program, expected) in programs {
+            let mut interpreter = Interpreter::new(false);
+            let statements = parse(program, interpreter.global_names());
+            let error = interpreter.interpret(&statements).unwrap_err();
+            assert!(
+                format!("{error:?}")
+                    .ends_with(&format!("AssertionError: Assertion failed: {expected}")),
+                "{error:?}"
+            );
+
+            let mut vm = Vm::new(false);
+            let statements = parse(program, vm.global_names());
+            let result = vm.interpret(&statements).unwrap_err();
+            assert_eq!(format!("{result:?}"), format!("{error:?}"));
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let program = "\
+            func fail(x) { return x[1]; }
+            func call(f) {
+                return f([]);
+            }
+            call(fail);
+        ";
+
+        let mut interpreter = Interpreter::new(false);
+        let statements = parse(program, interpreter.global_names());
+        let expected = interpreter.interpret(&statements).unwrap_err();
+
+        let mut vm = Vm::new(false);
+        let statements = parse(program, vm.global_names());
+        let error = vm.interpret(&statements).unwrap_err();
+
+        let frame = |function: &str, line| StackFrame {
+            function: function.to_string(),
+            line,
+        };
+        assert_eq!(error.trace(), [frame("call", 5), frame("fail", 3)]);
+        assert_eq!(error.trace(), expected.trace());
+        assert_eq!(
+            error.traceback("main.ck"),
+            "\
+Traceback (most recent call last):
+  File \"main.ck\", line 5, in <script>
+  File \"main.ck\", line 3, in call
+  File \"main.ck\", line 1, in fail
+"
+        );
+    }
+
+    #[test]
+    fn test_rethrow_trace() {
+        let program = "\
+            func fail(x) { return x[1]; }
+            func call(f) {
+                try { f([]); } catch e { throw e; }
+            }
+            try { call(fail); } catch e { throw e; }
+        ";
+
+        let mut interpreter = Interpreter::new(false);
+        let statements = parse(program, interpreter.global_names());
+        let expected = interpreter.interpret(&statements).unwrap_err();
+
+        let mut vm = Vm::new(false);
+        let statements = parse(program, vm.global_names());
+        let error = vm.interpret(&statements).unwrap_err();
+
+        let frame = |function: &str, line| StackFrame {
+            function: function.to_string(),
+            line,
+        };
+        assert_eq!(error.trace(), [frame("call", 5), frame("fail", 3)]);
+        assert_eq!(error.trace(), expected.trace());
+        assert_eq!(format!("{error:?}"), format!("{expected:?}"));
+    }
+}
@@ -0,0 +1,139 @@
+use std::rc::Rc;
+
+use crate::internal::ast::{Param, ParamKind, Pattern};
+use crate::internal::runtime::Value;
+use crate::internal::token::Token;
+
+/// A bytecode instruction. Operands are indices into the constants, tokens and
+/// functions of the chunk holding the instruction, or jump targets inside its
+/// code.
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    /// Pushes a constant.
+    Constant(u32),
+    /// Pushes `null`.
+    Null,
+    /// Discards the top of the stack.
+    Pop,
+    /// Pops the value of an expression statement and prints it in interactive
+    /// mode.
+    Statement,
+    /// Pops a value and prints it.
+    Echo,
+    /// Pushes the variable named by the token from the scope `depth` levels
+    /// outside the current one.
+    GetLocal {
+        depth: u32,
+        name: u32,
+    },
+    /// Assigns the top of the stack to a variable, leaving the value in place.
+    SetLocal {
+        depth: u32,
+        name: u32,
+    },
+    GetGlobal(u32),
+    SetGlobal(u32),
+    /// Pops a value and binds it to a name in the current scope.
+    Define(u32),
+    DeleteLocal {
+        depth: u32,
+        name: u32,
+    },
+    DeleteGlobal(u32),
+    /// Applies the operator token to the two values on top of the stack.
+    Binary(u32),
+    Unary(u32),
+    /// Applies an augmented assignment operator to a variable's value and the
+    /// right-hand side on top of it.
+    AugAssign(u32),
+    Prefix(u32),
+    Jump(u32),
+    /// Jumps if the top of the stack is falsy. The value is left in place.
+    JumpIfFalse(u32),
+    /// Jumps if the top of the stack is truthy. The value is left in place.
+    JumpIfTrue(u32),
+    /// Calls the callee below the given number of arguments.
+    Call {
+        argc: u32,
+        paren: u32,
+    },
+    /// Creates a function that captures the current scope.
+    Closure(u32),
+    Return,
+    List(u32),
+    Map {
+        len: u32,
+        brace: u32,
+    },
+    /// Joins the given number of values into a string.
+    Interpolation(u32),
+    Index(u32),
+    SetIndex(u32),
+    DeleteIndex(u32),
+    PushScope,
+    PopScope,
+    /// Replaces the top of the stack with the values to loop over and the
+    /// position of the next one.
+    Iterate(u32),
+    /// Applies the comparison operator token to the two values on top of the
+    /// stack, keeping them below the result.
+    Compare(u32),
+    /// Raises the error of a failed assertion with the message on top of the
+    /// stack. The source code of the test is a constant.
+    AssertionFailed {
+        keyword: u32,
+        source: u32,
+    },
+    /// Like `AssertionFailed`, for a failed comparison whose operands are
+    /// below the message.
+    ComparisonFailed {
+        operator: u32,
+        source: u32,
+    },
+    /// Pushes whether the value on top of the stack matches the pattern,
+    /// keeping the value in place.
+    Match(u32),
+    /// Pops a value and raises it as an error.
+    Throw(u32),
+    /// Catches errors raised until the matching `PopHandler` by jumping to the
+    /// target with the error on top of the stack.
+    PushHandler(u32),
+    PopHandler,
+    /// Pushes the next value of the loop on top of the stack, or jumps to the
+    /// target once every value has been visited.
+    Next(u32),
+}
+
+/// A sequence of instructions with the data they refer to.
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<Value>,
+    pub tokens: Vec<Token>,
+    pub functions: Vec<Rc<Function>>,
+    pub patterns: Vec<Pattern>,
+}
+
+/// A compiled function.
+pub struct Function {
+    // Anonymous functions and top-level scripts have no name
+    pub name: Option<Token>,
+    pub params: Vec<Param>,
+    // Positions of the code computing the default value of each optional
+    // parameter, followed by the start of the body
+    pub entries: Vec<usize>,
+    pub chunk: Chunk,
+}
+
+impl Function {
+    /// Returns where a call that bound the given number of parameters starts
+    /// running, skipping the default values it does not need.
+    pub fn entry(&self, bound: usize) -> usize {
+        let required = self
+            .params
+            .iter()
+            .filter(|param| param.kind == ParamKind::Required)
+            .count();
+        self.entries[bound - required]
+    }
+}
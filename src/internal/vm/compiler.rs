@@ -0,0 +1,651 @@
+use std::rc::Rc;
+
+use super::chunk::{Chunk, Function, Op};
+use crate::internal::ast::{Catch, Expr, ExprKind, Param, ParamKind, Pattern, Stmt, StmtKind};
+use crate::internal::runtime::{get_value, Value};
+use crate::internal::token::{token_type, Token, TokenType};
+
+/// Compiles resolved statements into bytecode.
+#[derive(Default)]
+pub struct Compiler<'a> {
+    chunk: Chunk,
+    // Enclosing loops of the function being compiled, innermost last
+    loops: Vec<Loop>,
+    // Enclosing parts of `try` statements, innermost last
+    guards: Vec<Guard<'a>>,
+}
+
+/// The jumps out of a loop that wait for their target to be known.
+struct Loop {
+    label: Option<String>,
+    // A `for` loop keeps a scope for its body and its iteration state on the
+    // stack, which must be dropped when jumping out of it
+    is_for: bool,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// Something a jump out of a `try` statement has to undo before leaving it.
+struct Guard<'a> {
+    // Number of loops enclosing the `try` statement
+    loops: usize,
+    cleanup: Cleanup<'a>,
+}
+
+enum Cleanup<'a> {
+    /// An exception handler, with the finally block to run after removing it
+    Handler(Option<&'a [Stmt]>),
+    /// The error kept on the stack while its finally block runs
+    Error,
+}
+
+impl<'a> Compiler<'a> {
+    /// Compiles the statements of a script into a function without parameters.
+    pub fn compile(statements: &[Stmt]) -> Function {
+        Compiler::default().function(None, Vec::new(), statements)
+    }
+
+    fn function(mut self, name: Option<Token>, params: Vec<Param>, body: &'a [Stmt]) -> Function {
+        // Default values are assigned in the scope of the call before the body
+        // runs
+        let mut entries = Vec::new();
+        for param in &params {
+            if let ParamKind::Optional(default) = &param.kind {
+                entries.push(self.chunk.code.len());
+                self.expression(default);
+                let name = self.token(&param.name);
+                self.emit(Op::Define(name));
+            }
+        }
+        entries.push(self.chunk.code.len());
+
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.emit(Op::Null);
+        self.emit(Op::Return);
+
+        Function {
+            name,
+            params,
+            entries,
+            chunk: self.chunk,
+        }
+    }
+
+    fn statement(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Function { name, params, body } => {
+                self.closure(Some(name), params, body);
+                let name = self.token(name);
+                self.emit(Op::Define(name));
+            }
+            StmtKind::While { label, test, body } => {
+                let start = self.chunk.code.len();
+                self.expression(test);
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+
+                let state = self.loop_body(label, false, body);
+                self.emit(Op::Jump(index(start)));
+
+                self.patch(exit);
+                self.emit(Op::Pop);
+                self.finish_loop(state, start);
+            }
+            StmtKind::For {
+                label,
+                name,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                let name = self.token(name);
+                self.emit(Op::Iterate(name));
+
+                // The loop variable only lives inside the loop body
+                let start = self.chunk.code.len();
+                let next = self.emit(Op::Next(0));
+                self.emit(Op::PushScope);
+                self.emit(Op::Define(name));
+
+                let mut state = self.loop_body(label, true, body);
+                let continues = std::mem::take(&mut state.continues);
+                for jump in continues {
+                    self.patch(jump);
+                }
+                self.emit(Op::PopScope);
+                self.emit(Op::Jump(index(start)));
+
+                self.patch(next);
+                let end = self.chunk.code.len();
+                self.emit(Op::Pop);
+                self.emit(Op::Pop);
+                for jump in state.breaks {
+                    self.patch_to(jump, end);
+                }
+            }
+            StmtKind::If {
+                test,
+                body,
+                or_else,
+            } => {
+                self.expression(test);
+                let otherwise = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                for stmt in body {
+                    self.statement(stmt);
+                }
+                let end = self.emit(Op::Jump(0));
+
+                self.patch(otherwise);
+                self.emit(Op::Pop);
+                for stmt in or_else.iter().flatten() {
+                    self.statement(stmt);
+                }
+                self.patch(end);
+            }
+            StmtKind::Break { label, .. } => self.loop_control(label, true),
+            StmtKind::Continue { label, .. } => self.loop_control(label, false),
+            StmtKind::Return { value, .. } => {
+                match value {
+                    Some(expr) => self.expression(expr),
+                    None => {
+                        self.emit(Op::Null);
+                    }
+                }
+
+                // The stack is dropped on return, so only handlers are undone
+                for i in (0..self.guards.len()).rev() {
+                    if let Cleanup::Handler(_) = self.guards[i].cleanup {
+                        self.leave_guard(i);
+                    }
+                }
+                self.emit(Op::Return);
+            }
+            StmtKind::Throw { keyword, value } => {
+                self.expression(value);
+                let keyword = self.token(keyword);
+                self.emit(Op::Throw(keyword));
+            }
+            StmtKind::Try {
+                keyword,
+                body,
+                catch,
+                finally,
+            } => self.try_statement(keyword, body, catch.as_ref(), finally.as_deref()),
+            StmtKind::Match { subject, arms, .. } => {
+                self.expression(subject);
+
+                let mut ends = Vec::new();
+                for arm in arms {
+                    // Jump to the body if any pattern matches, or to the next
+                    // arm after the last one fails
+                    let mut bodies = Vec::new();
+                    for pattern in &arm.patterns[..arm.patterns.len() - 1] {
+                        let pattern = self.pattern(pattern);
+                        self.emit(Op::Match(pattern));
+                        bodies.push(self.emit(Op::JumpIfTrue(0)));
+                        self.emit(Op::Pop);
+                    }
+                    let pattern = self.pattern(&arm.patterns[arm.patterns.len() - 1]);
+                    self.emit(Op::Match(pattern));
+                    let next = self.emit(Op::JumpIfFalse(0));
+
+                    for jump in bodies {
+                        self.patch(jump);
+                    }
+                    // Drop the test result and the matched value
+                    self.emit(Op::Pop);
+                    self.emit(Op::Pop);
+                    for stmt in &arm.body {
+                        self.statement(stmt);
+                    }
+                    ends.push(self.emit(Op::Jump(0)));
+
+                    self.patch(next);
+                    self.emit(Op::Pop);
+                }
+
+                self.emit(Op::Pop);
+                for jump in ends {
+                    self.patch(jump);
+                }
+            }
+            StmtKind::Assert {
+                keyword,
+                test,
+                source,
+                message,
+            } => {
+                let source = self.constant(Value::String(source.clone()));
+
+                // The operands of a comparison stay on the stack to show them
+                // if it fails
+                let (failed, kept) = match &test.kind {
+                    ExprKind::Binary(lhs, op, rhs) if token_type::is_comparison(op.ty) => {
+                        self.expression(lhs);
+                        self.expression(rhs);
+                        let operator = self.token(op);
+                        self.emit(Op::Compare(operator));
+                        (Op::ComparisonFailed { operator, source }, 2)
+                    }
+                    _ => {
+                        self.expression(test);
+                        let keyword = self.token(keyword);
+                        (Op::AssertionFailed { keyword, source }, 0)
+                    }
+                };
+
+                let passed = self.emit(Op::JumpIfTrue(0));
+                self.emit(Op::Pop);
+                match message {
+                    Some(expr) => self.expression(expr),
+                    None => {
+                        self.emit(Op::Null);
+                    }
+                }
+                self.emit(failed);
+
+                self.patch(passed);
+                for _ in 0..=kept {
+                    self.emit(Op::Pop);
+                }
+            }
+            StmtKind::Delete(targets) => {
+                for target in targets {
+                    match &target.kind {
+                        ExprKind::Variable { name, depth } => {
+                            let name = self.token(name);
+                            self.emit(match depth {
+                                Some(depth) => Op::DeleteLocal {
+                                    depth: index(*depth),
+                                    name,
+                                },
+                                None => Op::DeleteGlobal(name),
+                            });
+                        }
+                        ExprKind::Index {
+                            object,
+                            bracket,
+                            index,
+                        } => {
+                            self.expression(object);
+                            self.expression(index);
+                            let bracket = self.token(bracket);
+                            self.emit(Op::DeleteIndex(bracket));
+                        }
+                        _ => unreachable!("Parser only allows variables and subscripts"),
+                    }
+                }
+            }
+            StmtKind::Expr(expr) => {
+                self.expression(expr);
+                self.emit(Op::Statement);
+            }
+            StmtKind::Echo(expr) => {
+                self.expression(expr);
+                self.emit(Op::Echo);
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Binary(lhs, op, rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+                let op = self.token(op);
+                self.emit(Op::Binary(op));
+            }
+            ExprKind::Unary(op, rhs) => {
+                self.expression(rhs);
+                let op = self.token(op);
+                self.emit(Op::Unary(op));
+            }
+            ExprKind::Grouping(e) => self.expression(e),
+            ExprKind::Assign { name, value, depth } => {
+                self.expression(value);
+                self.set_variable(name, depth);
+            }
+            ExprKind::Logical(lhs, op, rhs) => {
+                self.expression(lhs);
+                let end = if op.ty == TokenType::DoubleVBar {
+                    self.emit(Op::JumpIfTrue(0))
+                } else {
+                    self.emit(Op::JumpIfFalse(0))
+                };
+                self.emit(Op::Pop);
+                self.expression(rhs);
+                self.patch(end);
+            }
+            ExprKind::Conditional {
+                test,
+                then,
+                or_else,
+            } => {
+                self.expression(test);
+                let otherwise = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.expression(then);
+                let end = self.emit(Op::Jump(0));
+
+                self.patch(otherwise);
+                self.emit(Op::Pop);
+                self.expression(or_else);
+                self.patch(end);
+            }
+            ExprKind::Call(callee, paren, arguments) => {
+                self.expression(callee);
+                for arg in arguments {
+                    self.expression(arg);
+                }
+                let paren = self.token(paren);
+                self.emit(Op::Call {
+                    argc: index(arguments.len()),
+                    paren,
+                });
+            }
+            ExprKind::Constant(literal) => {
+                let constant = self.constant(get_value(literal));
+                self.emit(Op::Constant(constant));
+            }
+            ExprKind::Variable { name, depth } => self.get_variable(name, depth),
+            ExprKind::Interpolation(parts) => {
+                for part in parts {
+                    self.expression(part);
+                }
+                self.emit(Op::Interpolation(index(parts.len())));
+            }
+            ExprKind::Lambda { params, body, .. } => self.closure(None, params, body),
+            ExprKind::List(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit(Op::List(index(elements.len())));
+            }
+            ExprKind::Map { brace, entries } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                let brace = self.token(brace);
+                self.emit(Op::Map {
+                    len: index(entries.len()),
+                    brace,
+                });
+            }
+            ExprKind::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                let bracket = self.token(bracket);
+                self.emit(Op::Index(bracket));
+            }
+            ExprKind::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                let bracket = self.token(bracket);
+                self.emit(Op::SetIndex(bracket));
+            }
+            ExprKind::AugAssign {
+                name,
+                operator,
+                value,
+                depth,
+            } => {
+                self.get_variable(name, depth);
+                self.expression(value);
+                let operator = self.token(operator);
+                self.emit(Op::AugAssign(operator));
+                self.set_variable(name, depth);
+            }
+            ExprKind::Prefix {
+                operator,
+                name,
+                depth,
+            } => {
+                self.get_variable(name, depth);
+                let operator = self.token(operator);
+                self.emit(Op::Prefix(operator));
+                self.set_variable(name, depth);
+            }
+        }
+    }
+
+    /// Compiles a `try` statement. The finally block is copied to every way out
+    /// of it: after the body, after the catch clause, before rethrowing an
+    /// uncaught error and before each jump out of the statement.
+    fn try_statement(
+        &mut self,
+        keyword: &Token,
+        body: &'a [Stmt],
+        catch: Option<&'a Catch>,
+        finally: Option<&'a [Stmt]>,
+    ) {
+        let mut ends = Vec::new();
+
+        let handler = self.emit(Op::PushHandler(0));
+        self.guarded(Cleanup::Handler(finally), body);
+        self.emit(Op::PopHandler);
+        self.statements(finally.unwrap_or_default());
+        ends.push(self.emit(Op::Jump(0)));
+
+        // The error is pushed on the stack when jumping to a handler
+        self.patch(handler);
+        if let Some(catch) = catch {
+            self.set_variable(&catch.name, &catch.depth);
+            self.emit(Op::Pop);
+
+            if let Some(finally) = finally {
+                let handler = self.emit(Op::PushHandler(0));
+                self.guarded(Cleanup::Handler(Some(finally)), &catch.body);
+                self.emit(Op::PopHandler);
+                self.statements(finally);
+                ends.push(self.emit(Op::Jump(0)));
+                self.patch(handler);
+            } else {
+                self.statements(&catch.body);
+                ends.push(self.emit(Op::Jump(0)));
+            }
+        }
+
+        if let Some(finally) = finally {
+            self.guarded(Cleanup::Error, finally);
+            let keyword = self.token(keyword);
+            self.emit(Op::Throw(keyword));
+        }
+
+        for jump in ends {
+            self.patch(jump);
+        }
+    }
+
+    /// Compiles statements inside a part of a `try` statement.
+    fn guarded(&mut self, cleanup: Cleanup<'a>, body: &'a [Stmt]) {
+        self.guards.push(Guard {
+            loops: self.loops.len(),
+            cleanup,
+        });
+        self.statements(body);
+        self.guards.pop();
+    }
+
+    /// Undoes a part of a `try` statement before jumping out of it.
+    fn leave_guard(&mut self, i: usize) {
+        match self.guards[i].cleanup {
+            Cleanup::Handler(finally) => {
+                self.emit(Op::PopHandler);
+
+                // Jumps in the finally block only see what encloses the `try`
+                // statement
+                let loops = self.loops.split_off(self.guards[i].loops);
+                let guards = self.guards.split_off(i);
+                self.statements(finally.unwrap_or_default());
+                self.loops.extend(loops);
+                self.guards.extend(guards);
+            }
+            Cleanup::Error => {
+                self.emit(Op::Pop);
+            }
+        }
+    }
+
+    fn statements(&mut self, statements: &'a [Stmt]) {
+        for stmt in statements {
+            self.statement(stmt);
+        }
+    }
+
+    /// Compiles a function and emits the instruction that creates it.
+    fn closure(&mut self, name: Option<&Token>, params: &[Param], body: &[Stmt]) {
+        let function = Compiler::default().function(name.cloned(), params.to_vec(), body);
+        self.chunk.functions.push(Rc::new(function));
+        self.emit(Op::Closure(index(self.chunk.functions.len() - 1)));
+    }
+
+    /// Compiles the body of a loop and returns the jumps that leave it.
+    fn loop_body(&mut self, label: &Option<Token>, is_for: bool, body: &'a [Stmt]) -> Loop {
+        self.loops.push(Loop {
+            label: label.as_ref().map(|l| l.lexeme.clone()),
+            is_for,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.loops.pop().expect("Loop was pushed above")
+    }
+
+    /// Points the jumps of a finished `while` loop to their targets.
+    fn finish_loop(&mut self, state: Loop, start: usize) {
+        for jump in state.continues {
+            self.patch_to(jump, start);
+        }
+        for jump in state.breaks {
+            self.patch(jump);
+        }
+    }
+
+    fn loop_control(&mut self, label: &Option<Token>, is_break: bool) {
+        let target = label.as_ref().map(|l| l.lexeme.clone());
+        let position = self
+            .loops
+            .iter()
+            .rposition(|l| target.is_none() || l.label == target)
+            .expect("Parser only allows loop control inside loops");
+
+        // Leave every loop and `try` statement nested inside the target loop,
+        // innermost first
+        let mut loops = self.loops.len();
+        for i in (0..self.guards.len()).rev() {
+            if self.guards[i].loops <= position {
+                break;
+            }
+            while loops > self.guards[i].loops {
+                loops -= 1;
+                self.leave_loop(loops);
+            }
+            self.leave_guard(i);
+        }
+        while loops > position + 1 {
+            loops -= 1;
+            self.leave_loop(loops);
+        }
+
+        if is_break {
+            if self.loops[position].is_for {
+                self.emit(Op::PopScope);
+            }
+            let jump = self.emit(Op::Jump(0));
+            self.loops[position].breaks.push(jump);
+        } else {
+            let jump = self.emit(Op::Jump(0));
+            self.loops[position].continues.push(jump);
+        }
+    }
+
+    /// Drops the state of a loop that is jumped out of.
+    fn leave_loop(&mut self, i: usize) {
+        if self.loops[i].is_for {
+            self.emit(Op::PopScope);
+            self.emit(Op::Pop);
+            self.emit(Op::Pop);
+        }
+    }
+
+    fn get_variable(&mut self, name: &Token, depth: &Option<usize>) {
+        let name = self.token(name);
+        self.emit(match depth {
+            Some(depth) => Op::GetLocal {
+                depth: index(*depth),
+                name,
+            },
+            None => Op::GetGlobal(name),
+        });
+    }
+
+    fn set_variable(&mut self, name: &Token, depth: &Option<usize>) {
+        let name = self.token(name);
+        self.emit(match depth {
+            Some(depth) => Op::SetLocal {
+                depth: index(*depth),
+                name,
+            },
+            None => Op::SetGlobal(name),
+        });
+    }
+
+    /// Appends an instruction and returns its position.
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    fn token(&mut self, token: &Token) -> u32 {
+        self.chunk.tokens.push(token.clone());
+        index(self.chunk.tokens.len() - 1)
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> u32 {
+        self.chunk.patterns.push(pattern.clone());
+        index(self.chunk.patterns.len() - 1)
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.chunk.constants.push(value);
+        index(self.chunk.constants.len() - 1)
+    }
+
+    /// Points the jump at the given position to the next instruction.
+    fn patch(&mut self, jump: usize) {
+        self.patch_to(jump, self.chunk.code.len());
+    }
+
+    fn patch_to(&mut self, jump: usize, target: usize) {
+        let target = index(target);
+        self.chunk.code[jump] = match self.chunk.code[jump] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::JumpIfTrue(_) => Op::JumpIfTrue(target),
+            Op::Next(_) => Op::Next(target),
+            Op::PushHandler(_) => Op::PushHandler(target),
+            op => unreachable!("{op:?} is not a jump"),
+        };
+    }
+}
+
+/// Converts a position or count into an instruction operand.
+fn index(n: usize) -> u32 {
+    n.try_into().expect("Chunk is too large")
+}
@@ -1,3 +1,7 @@
+use std::panic;
+use std::process::ExitCode;
+use std::thread;
+
 use clap::Parser;
 use rustyline::Result;
 
@@ -5,18 +9,35 @@ mod internal;
 mod repl;
 mod runner;
 
+use internal::runtime::STACK_SIZE;
+use runner::Backend;
+
 #[derive(Parser)]
 #[command(version)]
 struct Args {
     /// Path of the script file to run
     file: Option<String>,
+
+    /// Backend that executes the program
+    #[arg(long, value_enum, default_value_t)]
+    backend: Backend,
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
 
+    // Deep recursion in Chonk programs needs more stack than the main thread has
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| run(args))?
+        .join()
+        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+/// Runs the file given in the arguments, or the REPL if there is none.
+fn run(args: Args) -> Result<ExitCode> {
     if let Some(file) = args.file {
-        runner::run_file(&file);
+        Ok(runner::run_file(&file, args.backend))
     } else {
         let version = env!("CARGO_PKG_VERSION");
 
@@ -27,8 +48,7 @@ fn main() -> Result<()> {
             ",
             version
         );
-        repl::start()?;
+        repl::start(args.backend)?;
+        Ok(ExitCode::SUCCESS)
     }
-
-    Ok(())
 }
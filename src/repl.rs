@@ -6,8 +6,9 @@ use rustyline::validate::MatchingBracketValidator;
 use rustyline::{Completer, Helper, Highlighter, Hinter, Validator};
 use rustyline::{Editor, Result};
 
-use crate::internal::interpreter::Interpreter;
-use crate::runner;
+use crate::internal::diagnostic::Source;
+use crate::internal::parser::Parser;
+use crate::runner::{self, Backend};
 
 // This help template is from node :)
 const HELP_TEMPLATE: &str = "\
@@ -27,8 +28,13 @@ struct InputValidator {
 }
 
 /// Runs the interpreter interactively.
-pub fn start() -> Result<()> {
-    let mut interpreter = Interpreter::new(true);
+pub fn start(backend: Backend) -> Result<()> {
+    let mut engine = backend.engine(true);
+    // Every input run so far, so that errors in functions defined by earlier
+    // inputs can show where they are
+    let mut history = String::new();
+    // Number of lines in the history
+    let mut lines = 0;
 
     let helper = InputValidator {
         brackets: MatchingBracketValidator::new(),
@@ -63,16 +69,35 @@ pub fn start() -> Result<()> {
 
                 // Commands
                 match line.as_str() {
-                    ".clear" => interpreter = Interpreter::new(true),
+                    ".clear" => {
+                        engine = backend.engine(true);
+                        history.clear();
+                        lines = 0;
+                    }
                     ".exit" => running = false,
                     ".help" => println!("{}", HELP_TEMPLATE),
                     _ => {
-                        // Automatically add a missing semicolon
-                        if !line.ends_with(';') && !line.ends_with('}') {
+                        // Automatically add a missing semicolon. A closing brace
+                        // may end either a block or a map literal, so only add it
+                        // there if the result parses.
+                        if !line.ends_with(';')
+                            && (!line.ends_with('}')
+                                || Parser::new(&format!("{line};")).parse().is_ok())
+                        {
                             line.push(';');
                         }
 
-                        runner::run(&line, &mut interpreter);
+                        let start = history.len();
+                        history.push_str(&line);
+                        history.push('\n');
+
+                        let source = Source {
+                            name: "<repl>",
+                            text: &history,
+                        };
+                        // Errors are printed and the session goes on
+                        let _ = runner::run(&source, start, lines + 1, engine.as_mut());
+                        lines += line.matches('\n').count() + 1;
                     }
                 }
             }
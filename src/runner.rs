@@ -1,24 +1,166 @@
+use std::collections::HashSet;
 use std::fs;
+use std::process::ExitCode;
 
-use crate::internal::{interpreter::Interpreter, parser::Parser};
+use clap::ValueEnum;
 
-/// Reads a source file and executes it.
-pub fn run_file(path: &str) {
-    let mut interpreter = Interpreter::new(false);
-    let contents = fs::read_to_string(path).expect("Unable to read file");
-    run(&contents, &mut interpreter);
+use crate::internal::ast::Stmt;
+use crate::internal::diagnostic::Source;
+use crate::internal::runtime::RuntimeError;
+use crate::internal::{interpreter::Interpreter, parser::Parser, resolver::Resolver, vm::Vm};
+
+// Exit statuses from sysexits.h
+const EX_DATAERR: u8 = 65;
+const EX_SOFTWARE: u8 = 70;
+const EX_IOERR: u8 = 74;
+
+/// The backend that executes programs.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum Backend {
+    /// Tree-walk interpreter
+    #[default]
+    Interpreter,
+    /// Bytecode virtual machine
+    Vm,
+}
+
+impl Backend {
+    /// Creates a new engine of this backend.
+    pub fn engine(self, is_interactive: bool) -> Box<dyn Engine> {
+        match self {
+            Backend::Interpreter => Box::new(Interpreter::new(is_interactive)),
+            Backend::Vm => Box::new(Vm::new(is_interactive)),
+        }
+    }
+}
+
+/// Executes resolved programs while keeping their global variables.
+pub trait Engine {
+    /// Returns the names of all global variables.
+    fn global_names(&self) -> HashSet<String>;
+
+    /// Executes a list of resolved statements.
+    fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError>;
+}
+
+impl Engine for Interpreter {
+    fn global_names(&self) -> HashSet<String> {
+        self.global_names()
+    }
+
+    fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        self.interpret(statements)
+    }
+}
+
+impl Engine for Vm {
+    fn global_names(&self) -> HashSet<String> {
+        self.global_names()
+    }
+
+    fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        self.interpret(statements)
+    }
+}
+
+/// Why a program could not run to completion.
+pub enum Failure {
+    /// The program has lexical, syntax or resolution errors
+    Syntax,
+    /// The program stopped with a runtime error
+    Runtime,
+}
+
+impl Failure {
+    /// Returns the exit status of the process, following the BSD sysexits
+    /// convention.
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Failure::Syntax => ExitCode::from(EX_DATAERR),
+            Failure::Runtime => ExitCode::from(EX_SOFTWARE),
+        }
+    }
+}
+
+/// Reads a source file and executes it. Returns the exit status of the
+/// process.
+pub fn run_file(path: &str, backend: Backend) -> ExitCode {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            eprintln!("IOError: Cannot read \"{path}\": {error}");
+            return ExitCode::from(EX_IOERR);
+        }
+    };
+
+    let mut engine = backend.engine(false);
+    let source = Source {
+        name: path,
+        text: &contents,
+    };
+    match run(&source, 0, 1, engine.as_mut()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => failure.exit_code(),
+    }
 }
 
-/// Runs Chonk code.
-pub fn run(input: &str, interpreter: &mut Interpreter) {
-    let mut parser = Parser::new(input);
+/// Runs the Chonk code starting at the given byte offset and line of the
+/// source and prints any errors found in it. The text before the offset is code
+/// that ran earlier, which diagnostics about functions defined there point
+/// into.
+pub fn run(
+    source: &Source,
+    start: usize,
+    line: usize,
+    engine: &mut dyn Engine,
+) -> Result<(), Failure> {
+    let mut parser = Parser::starting_at(&source.text[start..], start, line);
+    let result = parser.parse();
+    for warning in parser.warnings() {
+        warning.emit(source);
+    }
 
-    match parser.parse() {
-        Ok(statements) => {
-            if let Err(error) = interpreter.interpret(&statements) {
-                eprintln!("{error:?}");
+    let mut statements = match result {
+        Ok(statements) => statements,
+        Err(errors) => {
+            for error in errors {
+                error.diagnostic().emit(source);
             }
+            return Err(Failure::Syntax);
         }
-        Err(error) => eprintln!("{error:?}"),
+    };
+
+    let mut resolver = Resolver::new(engine.global_names());
+    if let Err(error) = resolver.resolve(&mut statements) {
+        error.diagnostic().emit(source);
+        return Err(Failure::Syntax);
+    }
+
+    if let Err(error) = engine.interpret(&statements) {
+        eprint!("{}", error.traceback(source.name));
+        error.diagnostic().emit(source);
+        return Err(Failure::Runtime);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failure() {
+        let mut engine = Backend::Interpreter.engine(false);
+        let mut run_text = |text| {
+            let source = Source { name: "test", text };
+            run(&source, 0, 1, engine.as_mut())
+        };
+
+        assert!(run_text("a = 1;").is_ok());
+        assert!(matches!(run_text("a = ;"), Err(Failure::Syntax)));
+        assert!(matches!(run_text("a = \"b"), Err(Failure::Syntax)));
+        assert!(matches!(run_text("return a;"), Err(Failure::Syntax)));
+        assert!(matches!(run_text("a[0];"), Err(Failure::Runtime)));
     }
 }